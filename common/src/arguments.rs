@@ -0,0 +1,59 @@
+/// Joins command-line arguments into a single line, quoting the ones that contain whitespace
+/// or quotes, so they survive being sent over the socket.
+pub fn join_arguments<S: AsRef<str>>(arguments: &[S]) -> String {
+    arguments.iter().map(|a| quote_argument(a.as_ref())).collect::<Vec<_>>().join(" ")
+}
+
+pub fn quote_argument(argument: &str) -> String {
+    let needs_quotes = argument.is_empty() || argument.chars().any(|c| c.is_whitespace() || c == '"' || c == '\'' || c == '\\');
+    if !needs_quotes {
+        return argument.to_string();
+    }
+
+    let escaped = argument.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
+
+/// Splits a line into arguments, honoring single quotes, double quotes and backslash escapes.
+pub fn split_arguments(line: &str) -> Result<Vec<String>, String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut in_argument = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('\''), '\'') => quote = None,
+            (Some('"'), '"') => quote = None,
+            (Some('"') | None, '\\') => {
+                current.push(chars.next().ok_or("Unterminated escape sequence")?);
+                in_argument = true;
+            }
+            (Some(_), c) => current.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_argument = true;
+            }
+            (None, c) if c.is_whitespace() => {
+                if in_argument {
+                    arguments.push(std::mem::take(&mut current));
+                    in_argument = false;
+                }
+            }
+            (None, c) => {
+                current.push(c);
+                in_argument = true;
+            }
+        }
+    }
+
+    if quote.is_some() {
+        return Err("Unterminated quote".to_string());
+    }
+    if in_argument {
+        arguments.push(current);
+    }
+
+    Ok(arguments)
+}
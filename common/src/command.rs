@@ -1,4 +1,5 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Parser)]
 #[command(name = "ovpn")]
@@ -18,26 +19,150 @@ pub enum Commands {
         #[command(subcommand)]
         commands: SessionCommand
     },
+
+    Daemon {
+        #[command(subcommand)]
+        commands: DaemonCommand
+    },
 }
 
 #[derive(Debug, Subcommand)]
 pub enum ConfigCommand {
     List,
+    /// Import a single config file, a directory, or a .zip/.tar.gz archive of config files
     Import {
+        /// Name of the imported config, or the name prefix when importing multiple files
         #[arg(short, long)]
         name: Option<String>,
 
         #[arg(short, long)]
         path: String,
+
+        /// What to do when a configuration with the same name already exists
+        #[arg(short, long, value_enum)]
+        conflict: Option<ConflictStrategy>,
+
+        /// Only report what would be imported
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Store the config as a template with {{var}} placeholders, to be used by profiles
+        #[arg(long)]
+        template: bool,
+    },
+    /// Create a profile of a template, or update the variables of an existing profile
+    Profile {
+        #[arg(short, long)]
+        name: String,
+
+        #[arg(short, long)]
+        template: String,
+
+        /// Template variable in the form key=value, can be repeated
+        #[arg(short, long = "var", value_parser = parse_variable)]
+        variables: Vec<(String, String)>,
     },
     Export {
         #[arg(short, long)]
         name: String,
+
+        #[arg(short, long, value_enum, default_value_t = ExportFormat::Config)]
+        format: ExportFormat,
+
+        /// Include referenced credentials files (e.g. auth-user-pass) in the bundle
+        #[arg(long)]
+        include_credentials: bool,
+
+        /// File the bundle is written to (defaults to <name>.bundle.tar.gz)
+        #[arg(short, long)]
+        output: Option<String>,
     },
     Delete {
         #[arg(short, long)]
         name: String,
     },
+    /// Change the settings of a configuration
+    Set {
+        #[arg(short, long)]
+        name: String,
+
+        /// Extra openvpn argument with its parameters, e.g. --arg "--verb 4", can be repeated
+        #[arg(long = "arg", allow_hyphen_values = true)]
+        arguments: Vec<String>,
+
+        /// Environment variable for openvpn in the form key=value, can be repeated
+        #[arg(long = "env", value_parser = parse_variable)]
+        environment: Vec<(String, String)>,
+
+        /// Remove all extra arguments before adding the given ones
+        #[arg(long)]
+        clear_args: bool,
+
+        /// Remove all environment variables before adding the given ones
+        #[arg(long)]
+        clear_env: bool,
+
+        /// Which traffic is routed through the tunnel
+        #[arg(long, value_enum)]
+        route_mode: Option<RouteMode>,
+
+        /// CIDR, IP address or host name used by the include/exclude route modes, can be repeated
+        #[arg(long = "route")]
+        routes: Vec<String>,
+
+        /// Remove all routes before adding the given ones
+        #[arg(long)]
+        clear_routes: bool,
+
+        /// Block all traffic outside the tunnel while the session is up or reconnecting
+        #[arg(long)]
+        kill_switch: Option<bool>,
+
+        /// Absolute path of an executable run on session status changes, can be repeated
+        #[arg(long = "hook")]
+        hooks: Vec<String>,
+
+        /// Remove all hooks before adding the given ones
+        #[arg(long)]
+        clear_hooks: bool,
+
+        /// Start a session with this config when the daemon starts (with the autostart startup policy)
+        #[arg(long)]
+        autostart: Option<bool>,
+    },
+    /// Check the config index and stored files for inconsistencies
+    Fsck {
+        /// Fix the reported problems instead of only listing them
+        #[arg(long)]
+        repair: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ConflictStrategy {
+    Skip,
+    Rename,
+    Overwrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum ExportFormat {
+    /// The raw configuration text
+    Config,
+    /// A .tar.gz archive with the config, referenced files and index metadata
+    Bundle,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, ValueEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RouteMode {
+    /// Accept the routes pushed by the server, including redirect-gateway
+    #[default]
+    Full,
+    /// Only route the given networks through the tunnel
+    Include,
+    /// Route everything through the tunnel, except the given networks
+    Exclude,
 }
 
 #[derive(Debug, Subcommand)]
@@ -48,4 +173,32 @@ pub enum SessionCommand {
     },
     Stop,
     Status,
-}
\ No newline at end of file
+    /// List past sessions, newest first
+    History {
+        /// Only sessions of this configuration
+        #[arg(short, long)]
+        name: Option<String>,
+
+        /// Only sessions started after this time: an RFC 3339 timestamp, a date (YYYY-MM-DD),
+        /// or a duration ago such as 30m, 12h or 7d
+        #[arg(long)]
+        since: Option<String>,
+
+        /// Maximum number of sessions to list
+        #[arg(short, long)]
+        limit: Option<usize>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub enum DaemonCommand {
+    /// Re-read the daemon configuration and the config index, like SIGHUP
+    Reload,
+}
+
+fn parse_variable(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err(format!("Invalid variable '{}', expected key=value", value)),
+    }
+}
@@ -1,2 +1,3 @@
+pub mod arguments;
 pub mod command;
 pub mod paths;
\ No newline at end of file
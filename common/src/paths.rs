@@ -1,7 +1,17 @@
 pub const SOCKET_PATH: &str = "/run/ovpnd-daemon.sock";
 pub const CONFIGS_PATH: &str = "/etc/ovpn/configs";
 pub const OPENVPN_PATH: &str = "/usr/bin/openvpn";
+pub const RUNTIME_PATH: &str = "/run/ovpn";
+pub const DAEMON_CONFIG_PATH: &str = "/etc/ovpn/ovpnd.conf";
+pub const HOOKS_PATH: &str = "/etc/ovpn/hooks.d";
+pub const STATE_PATH: &str = "/var/lib/ovpn";
+pub const HISTORY_PATH: &str = "/var/lib/ovpn/history.jsonl";
 
 // pub const SOCKET_PATH: &str = "./ovpnd-daemon.sock";
 // pub const CONFIGS_PATH: &str = "./configs";
 // pub const OPENVPN_PATH: &str = "./openvpn";
+// pub const RUNTIME_PATH: &str = "./run";
+// pub const DAEMON_CONFIG_PATH: &str = "./ovpnd.conf";
+// pub const HOOKS_PATH: &str = "./hooks.d";
+// pub const STATE_PATH: &str = "./state";
+// pub const HISTORY_PATH: &str = "./state/history.jsonl";
@@ -0,0 +1,232 @@
+use std::io;
+use std::io::Write;
+use std::sync::mpsc::RecvTimeoutError;
+use std::thread::sleep;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use crate::client::{Client, Message};
+
+/// The daemon samples the traffic counters this often, so they are considered idle only after
+/// not changing for longer.
+const IDLE_AFTER: Duration = Duration::from_secs(12);
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum BarFormat {
+    /// JSON with text, tooltip, class and percentage, for a custom module with `return-type: json`
+    Waybar,
+    /// Plain lines, for a script module with `tail = true`
+    Polybar,
+    /// Plain lines, for a block with `interval=persist`
+    I3blocks,
+}
+
+/// The format strings, see `ovpn-cli bar --help` for the placeholders.
+pub struct BarTemplates {
+    pub text: String,
+    pub tooltip: String,
+    pub disconnected: String,
+}
+
+/// The active session as far as the bar is concerned.
+struct Status {
+    state: String,
+    name: String,
+    started: u64,
+    local_ip: String,
+    received: u64,
+    sent: u64,
+}
+
+/// Traffic counters at the time they last changed, to compute the throughput.
+struct Sample {
+    name: String,
+    at: Instant,
+    received: u64,
+    sent: u64,
+}
+
+/// Prints the status of the active session for a status bar, one line whenever it changes.
+pub struct Bar {
+    format: BarFormat,
+    templates: BarTemplates,
+    interval: Duration,
+    sample: Option<Sample>,
+    rates: (f64, f64),
+    last_line: String,
+}
+
+impl Bar {
+    pub fn new(format: BarFormat, templates: BarTemplates, interval: Duration) -> Bar {
+        Bar { format, templates, interval, sample: None, rates: (0.0, 0.0), last_line: String::new() }
+    }
+
+    /// Follows the daemon until the bar stops reading the output. While the daemon is not
+    /// running the state is `Unavailable`, and connecting is retried every interval.
+    pub fn run(&mut self) -> io::Result<()> {
+        loop {
+            // Only failing to write the output ends the bar, losing the daemon does not
+            if let Ok(client) = Client::connect() {
+                match self.follow(client) {
+                    Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Err(e),
+                    _ => {}
+                }
+            }
+
+            self.print(None, "Unavailable")?;
+            sleep(self.interval);
+        }
+    }
+
+    /// Asks for the status on every event and every interval, until the connection fails.
+    fn follow(&mut self, mut client: Client) -> io::Result<()> {
+        let messages = client.spawn_reader()?;
+        client.send("session status")?;
+
+        loop {
+            match messages.recv_timeout(self.interval) {
+                Ok(Ok(Message::Response { success: true, message })) => {
+                    let status = Self::parse_status(&message);
+                    self.update_rates(status.as_ref());
+                    self.print(status.as_ref(), "Disconnected")?;
+                }
+                Ok(Ok(Message::Event(_))) | Err(RecvTimeoutError::Timeout) => client.send("session status")?,
+                Ok(Ok(Message::Response { success: false, .. })) => {}
+                Ok(Err(e)) => return Err(e),
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+
+    /// Parses the response of `session status`, which is not JSON without an active session.
+    fn parse_status(message: &str) -> Option<Status> {
+        let session: Value = serde_json::from_str(message).ok()?;
+        let connection = &session["connection"];
+        Some(Status {
+            state: session["status"].as_str()?.to_string(),
+            name: session["config"]["name"].as_str()?.to_string(),
+            started: session["started"].as_u64().unwrap_or_default(),
+            local_ip: connection["local_ip"].as_str().unwrap_or_default().to_string(),
+            received: connection["bytes_received"].as_u64().unwrap_or_default(),
+            sent: connection["bytes_sent"].as_u64().unwrap_or_default(),
+        })
+    }
+
+    fn update_rates(&mut self, status: Option<&Status>) {
+        let Some(status) = status else {
+            self.sample = None;
+            self.rates = (0.0, 0.0);
+            return;
+        };
+
+        let now = Instant::now();
+        match &self.sample {
+            Some(sample) if sample.name == status.name && status.received >= sample.received && status.sent >= sample.sent => {
+                let elapsed = now.duration_since(sample.at);
+                if (status.received, status.sent) != (sample.received, sample.sent) {
+                    let seconds = elapsed.as_secs_f64().max(1.0);
+                    self.rates = ((status.received - sample.received) as f64 / seconds, (status.sent - sample.sent) as f64 / seconds);
+                } else {
+                    if elapsed > IDLE_AFTER {
+                        self.rates = (0.0, 0.0);
+                    }
+                    return;
+                }
+            }
+            _ => self.rates = (0.0, 0.0),
+        }
+        self.sample = Some(Sample { name: status.name.clone(), at: now, received: status.received, sent: status.sent });
+    }
+
+    /// Prints the line for the status, unless it is the same as the previous one. `inactive`
+    /// is the state shown without a session.
+    fn print(&mut self, status: Option<&Status>, inactive: &str) -> io::Result<()> {
+        let (state, percentage) = match status {
+            Some(status) => (status.state.as_str(), match status.state.as_str() {
+                "Running" => 100,
+                "Starting" | "Stopping" => 50,
+                _ => 0,
+            }),
+            None => (inactive, 0),
+        };
+        let is_active = matches!(state, "Starting" | "Running" | "Stopping");
+
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let values = [
+            ("state", state.to_string()),
+            ("name", status.map(|s| s.name.clone()).unwrap_or_default()),
+            ("uptime", status.filter(|_| is_active).map(|s| format_duration(now.saturating_sub(s.started))).unwrap_or_default()),
+            ("ip", status.map(|s| s.local_ip.clone()).unwrap_or_default()),
+            ("down", format!("{}/s", format_bytes(self.rates.0 as u64))),
+            ("up", format!("{}/s", format_bytes(self.rates.1 as u64))),
+            ("received", format_bytes(status.map(|s| s.received).unwrap_or_default())),
+            ("sent", format_bytes(status.map(|s| s.sent).unwrap_or_default())),
+        ];
+
+        let (text_template, tooltip_template) = match is_active {
+            true => (&self.templates.text, &self.templates.tooltip),
+            false => (&self.templates.disconnected, &self.templates.disconnected),
+        };
+        let line = match self.format {
+            BarFormat::Waybar => {
+                // Waybar renders text and tooltips as Pango markup
+                let values = values.map(|(key, value)| (key, escape_markup(&value)));
+                json!({
+                    "text": render(text_template, &values),
+                    "tooltip": render(tooltip_template, &values),
+                    "class": state.to_lowercase(),
+                    "percentage": percentage,
+                }).to_string()
+            }
+            BarFormat::Polybar | BarFormat::I3blocks => render(text_template, &values).replace('\n', " "),
+        };
+
+        if line == self.last_line {
+            return Ok(());
+        }
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()?;
+        self.last_line = line;
+        Ok(())
+    }
+}
+
+/// Replaces each `{key}` in the template with its value, and `\n` with a line break.
+fn render(template: &str, values: &[(&str, String)]) -> String {
+    let mut output = template.replace("\\n", "\n");
+    for (key, value) in values {
+        output = output.replace(&format!("{{{}}}", key), value);
+    }
+    output
+}
+
+fn escape_markup(value: &str) -> String {
+    value.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+/// Formats a duration as its two largest units, e.g. `45s`, `12m 05s` or `1h 02m`.
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes, seconds) = (seconds / 86400, seconds / 3600 % 24, seconds / 60 % 60, seconds % 60);
+    match (days, hours, minutes) {
+        (0, 0, 0) => format!("{}s", seconds),
+        (0, 0, _) => format!("{}m {:02}s", minutes, seconds),
+        (0, _, _) => format!("{}h {:02}m", hours, minutes),
+        _ => format!("{}d {:02}h", days, hours),
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1000.0 && unit < UNITS.len() - 1 {
+        value /= 1000.0;
+        unit += 1;
+    }
+
+    match unit {
+        0 => format!("{} {}", bytes, UNITS[0]),
+        _ => format!("{:.1} {}", value, UNITS[unit]),
+    }
+}
@@ -0,0 +1,127 @@
+use std::io;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+use common::paths::SOCKET_PATH;
+
+/// A status change broadcast by the daemon, see the socket protocol in the README.
+#[derive(Debug, Clone)]
+pub enum Event {
+    Session { guid: String, name: String, status: String },
+    Daemon { kind: String, message: String },
+}
+
+/// Something the daemon sent: the response to a command, or an event.
+#[derive(Debug)]
+pub enum Message {
+    Response { success: bool, message: String },
+    Event(Event),
+}
+
+/// A connection to the daemon socket.
+pub struct Client {
+    writer: UnixStream,
+    reader: BufReader<UnixStream>,
+}
+
+impl Client {
+    pub fn connect() -> io::Result<Client> {
+        let stream = UnixStream::connect(SOCKET_PATH)?;
+        Ok(Client {
+            writer: stream.try_clone()?,
+            reader: BufReader::new(stream),
+        })
+    }
+
+    pub fn send(&mut self, command: &str) -> io::Result<()> {
+        self.writer.write_all(format!("{}\n", command).as_bytes())
+    }
+
+    /// Sends a command and waits for its response, skipping the events that arrive meanwhile.
+    pub fn request(&mut self, command: &str) -> io::Result<(bool, String)> {
+        self.send(command)?;
+        loop {
+            if let Message::Response { success, message } = self.read_message()? {
+                return Ok((success, message));
+            }
+        }
+    }
+
+    /// Reads the next message, failing with `UnexpectedEof` once the daemon closed the connection.
+    pub fn read_message(&mut self) -> io::Result<Message> {
+        let mut response_length: usize = 0;
+        let mut response_status = String::new();
+        let mut response_message = String::new();
+        let mut bytes_read: usize = 0;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "The daemon closed the connection"));
+            }
+            let line = line.trim_end_matches('\n');
+
+            // Events can arrive while a multi-line response is being written
+            if let Some(event) = line.strip_prefix('!') {
+                match Self::parse_event(event) {
+                    Some(event) if response_length == 0 => return Ok(Message::Event(event)),
+                    _ => continue,
+                }
+            }
+
+            if response_length == 0 {
+                // The first line is in the format: <length>:<status>:<message>
+                let parts: Vec<&str> = line.splitn(3, ':').collect();
+                if parts.len() == 3 {
+                    response_length = parts[0].parse().unwrap_or(0);
+                    response_status = parts[1].to_string();
+                    response_message.push_str(parts[2]);
+                    bytes_read = response_status.len() + 1 + response_message.len();
+                }
+            } else {
+                response_message.push('\n');
+                response_message.push_str(line);
+                bytes_read += line.len() + 1;
+            }
+
+            if bytes_read >= response_length {
+                return Ok(Message::Response { success: response_status != "err", message: response_message });
+            }
+        }
+    }
+
+    /// Reads the messages on a separate thread, so the caller can wait for them with a timeout.
+    /// Commands can still be sent through the client, but nothing must be read from it anymore.
+    pub fn spawn_reader(&self) -> io::Result<Receiver<io::Result<Message>>> {
+        let mut reader = Client {
+            writer: self.writer.try_clone()?,
+            reader: BufReader::new(self.writer.try_clone()?),
+        };
+        let (sender, receiver) = channel();
+        thread::spawn(move || loop {
+            let message = reader.read_message();
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed {
+                return;
+            }
+        });
+
+        Ok(receiver)
+    }
+
+    /// Parses an event line without its `!`: `<length>:<guid>:<name>:<status>` or
+    /// `<length>:daemon:<kind>:<message>`.
+    fn parse_event(line: &str) -> Option<Event> {
+        let (_, event) = line.split_once(':')?;
+        if let Some(event) = event.strip_prefix("daemon:") {
+            let (kind, message) = event.split_once(':')?;
+            return Some(Event::Daemon { kind: kind.to_string(), message: message.to_string() });
+        }
+
+        // Names can contain colons, the guid and the status cannot
+        let (guid, rest) = event.split_once(':')?;
+        let (name, status) = rest.rsplit_once(':')?;
+        Some(Event::Session { guid: guid.to_string(), name: name.to_string(), status: status.to_string() })
+    }
+}
@@ -0,0 +1,163 @@
+use std::io;
+use std::io::Write;
+use chrono::Local;
+use clap::ValueEnum;
+use serde_json::{json, Value};
+use crate::client::{Client, Event, Message};
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum EventFilter {
+    /// Status changes of sessions
+    Session,
+    /// Configs that were imported, updated or deleted
+    Config,
+    /// Other daemon events, such as autostart and stopping
+    Daemon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum UntilStatus {
+    Starting,
+    Running,
+    Stopping,
+    Stopped,
+    Failed,
+}
+
+/// Prints the events broadcast by the daemon as they arrive.
+pub struct EventWatch {
+    pub json: bool,
+
+    /// Categories that are printed, all of them if empty.
+    pub filters: Vec<EventFilter>,
+
+    /// Exit once a session reaches this status.
+    pub until: Option<UntilStatus>,
+
+    /// Only session events of this config are printed and matched.
+    pub name: Option<String>,
+}
+
+impl EventWatch {
+    /// Follows the events, failing once the daemon goes away. Returns when the `until`
+    /// condition is met, immediately if it already holds.
+    pub fn run(&self) -> io::Result<()> {
+        let mut client = Client::connect()?;
+
+        // Asked on the same connection, so no event can be missed between the answer and the
+        // events that follow it
+        if self.until.is_some() {
+            client.send("session status")?;
+        }
+
+        loop {
+            match client.read_message()? {
+                Message::Response { success: true, message } => if self.holds(&message) {
+                    return Ok(());
+                },
+                Message::Response { success: false, .. } => {}
+                Message::Event(event) => {
+                    if self.is_shown(&event) {
+                        self.print(&event)?;
+                    }
+                    // Checked apart from the filters, which may hide the session events
+                    if self.reaches_until(&event) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+    }
+
+    fn category(event: &Event) -> EventFilter {
+        match event {
+            Event::Session { .. } => EventFilter::Session,
+            Event::Daemon { kind, .. } if kind == "config" => EventFilter::Config,
+            Event::Daemon { .. } => EventFilter::Daemon,
+        }
+    }
+
+    fn is_shown(&self, event: &Event) -> bool {
+        if !self.filters.is_empty() && !self.filters.contains(&Self::category(event)) {
+            return false;
+        }
+
+        match event {
+            Event::Session { name, .. } => self.is_named(name),
+            Event::Daemon { .. } => true,
+        }
+    }
+
+    /// Whether a session event reaches the `until` status, whatever the filters are.
+    fn reaches_until(&self, event: &Event) -> bool {
+        match (event, self.until) {
+            (Event::Session { name, status, .. }, Some(until)) => self.is_named(name) && Self::is_status(until, status),
+            _ => false,
+        }
+    }
+
+    fn is_named(&self, name: &str) -> bool {
+        self.name.as_ref().is_none_or(|expected| expected == name)
+    }
+
+    fn is_status(until: UntilStatus, status: &str) -> bool {
+        format!("{:?}", until) == status
+    }
+
+    /// Whether the condition already holds, judging by the response of `session status`.
+    fn holds(&self, message: &str) -> bool {
+        let Some(until) = self.until else { return false };
+        let Ok(session) = serde_json::from_str::<Value>(message) else {
+            // No session is active
+            return until == UntilStatus::Stopped;
+        };
+
+        let name = session["config"]["name"].as_str().unwrap_or_default();
+        match &self.name {
+            Some(expected) if expected != name => until == UntilStatus::Stopped,
+            _ => Self::is_status(until, session["status"].as_str().unwrap_or_default()),
+        }
+    }
+
+    fn print(&self, event: &Event) -> io::Result<()> {
+        let timestamp = Local::now().format("%Y-%m-%dT%H:%M:%S%:z").to_string();
+        let line = match (event, self.json) {
+            (Event::Session { guid, name, status }, true) => {
+                json!({ "timestamp": timestamp, "type": "session", "guid": guid, "name": name, "status": status }).to_string()
+            }
+            (Event::Session { name, status, .. }, false) => format!("{} session {} {}", timestamp, name, status),
+            (Event::Daemon { message, .. }, true) if Self::category(event) == EventFilter::Config => {
+                json!({ "timestamp": timestamp, "type": "config", "message": message }).to_string()
+            }
+            (Event::Daemon { message, .. }, false) if Self::category(event) == EventFilter::Config => format!("{} config {}", timestamp, message),
+            (Event::Daemon { kind, message }, true) => {
+                json!({ "timestamp": timestamp, "type": "daemon", "kind": kind, "message": message }).to_string()
+            }
+            (Event::Daemon { kind, message }, false) => format!("{} daemon {}: {}", timestamp, kind, message),
+        };
+
+        let mut stdout = io::stdout().lock();
+        writeln!(stdout, "{}", line)?;
+        stdout.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(name: &str, status: &str) -> Event {
+        Event::Session { guid: String::new(), name: name.to_string(), status: status.to_string() }
+    }
+
+    #[test]
+    fn until_ignores_the_filters() {
+        let watch = EventWatch { json: false, filters: vec![EventFilter::Config], until: Some(UntilStatus::Running), name: Some("x".to_string()) };
+
+        assert!(!watch.is_shown(&session("x", "Running")));
+        assert!(watch.reaches_until(&session("x", "Running")));
+        assert!(!watch.reaches_until(&session("x", "Starting")));
+        assert!(!watch.reaches_until(&session("y", "Running")));
+        assert!(!watch.reaches_until(&Event::Daemon { kind: "config".to_string(), message: "Running".to_string() }));
+    }
+}
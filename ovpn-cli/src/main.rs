@@ -1,63 +1,105 @@
-use std::{env, io};
-use std::io::{BufRead, BufReader, Write};
-use std::os::unix::net::UnixStream;
-use common::command::Cli;
-use clap::Parser;
-use common::paths::SOCKET_PATH;
+use std::{env, fs, io};
+use std::time::Duration;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use clap::{Parser, Subcommand};
+use common::arguments::join_arguments;
+use common::command::{Commands, ConfigCommand, ExportFormat};
+use crate::bar::{Bar, BarFormat, BarTemplates};
+use crate::client::Client;
+use crate::events::{EventFilter, EventWatch, UntilStatus};
 
-fn main() -> io::Result<()> {
-    _ = Cli::parse();
+mod bar;
+mod client;
+mod events;
 
-    let args: Vec<String> = env::args().skip(1).collect();
-    let command = format!("{}\n", args.join(" "));
+/// The daemon commands, and the ones that are handled by the CLI itself.
+#[derive(Debug, Parser)]
+#[command(name = "ovpn")]
+struct Cli {
+    #[command(subcommand)]
+    commands: CliCommands,
+}
 
-    let mut stream = UnixStream::connect(SOCKET_PATH)?;
-    stream.write_all(command.as_bytes())?;
+#[derive(Debug, Subcommand)]
+enum CliCommands {
+    #[command(flatten)]
+    Daemon(Commands),
 
-    // Create a buffer reader to handle multiple lines
-    let reader = BufReader::new(stream);
+    /// Print the status of the active session for a status bar, one line whenever it changes.
+    /// Format strings can use {state}, {name}, {uptime}, {ip}, {down} and {up} (throughput),
+    /// {received} and {sent} (totals), and \n for a line break
+    Bar {
+        #[arg(short, long, value_enum, default_value_t = BarFormat::Waybar)]
+        format: BarFormat,
 
-    // Variables to store the response components
-    let mut response_length: usize = 0;
-    let mut response_status = String::new();
-    let mut response_message = String::new();
-    let mut bytes_read: usize = 0;
+        /// Text while a session is starting, running or stopping
+        #[arg(long, default_value = "{name} ↓{down} ↑{up}")]
+        text: String,
 
-    // Read the response line by line
-    for line in reader.lines() {
-        let line = line?;
+        /// Text and tooltip without an active session, or while the daemon is not running
+        #[arg(long, default_value = "VPN {state}")]
+        text_disconnected: String,
 
-        // Ignore event messages starting with '!'
-        if line.starts_with('!') {
-            continue;
-        }
+        /// Tooltip while a session is active, for waybar
+        #[arg(long, default_value = "{name}: {state}\\nUp {uptime}, {ip}\\n↓ {down} ({received})\\n↑ {up} ({sent})")]
+        tooltip: String,
 
-        // Check if this is the command response
-        if response_length == 0 {
-            // Parse the first line which should be in the format: <length>:<status>:<message>
-            let parts: Vec<&str> = line.splitn(3, ':').collect();
-            if parts.len() == 3 {
-                response_length = parts[0].parse().unwrap_or(0);
-                response_status = parts[1].to_string();
-                response_message.push_str(parts[2]);
-                bytes_read = response_status.len() + 1 + response_message.len(); // Include the ':' and the message
-            }
-        } else {
-            // Accumulate the rest of the message
-            response_message.push('\n');
-            response_message.push_str(&line);
-            bytes_read += line.len() + 1; // +1 for the newline character
-        }
+        /// Seconds between updates of the uptime and throughput, status changes are shown immediately
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+
+    /// Print the events of the daemon with timestamps as they arrive. Fails when the daemon goes away
+    Events {
+        /// Print each event as a JSON object
+        #[arg(long)]
+        json: bool,
+
+        /// Only print events of this kind, can be repeated
+        #[arg(long, value_enum)]
+        filter: Vec<EventFilter>,
+
+        /// Exit once a session reaches this status, immediately if it already did
+        #[arg(long, value_enum)]
+        until: Option<UntilStatus>,
+
+        /// Only session events of this configuration
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+}
+
+fn main() -> io::Result<()> {
+    let cli = Cli::parse();
 
-        // Stop reading if we've read the complete response
-        if bytes_read >= response_length {
-            break;
+    let commands = match cli.commands {
+        CliCommands::Daemon(commands) => commands,
+        CliCommands::Bar { format, text, text_disconnected, tooltip, interval } => {
+            let templates = BarTemplates { text, tooltip, disconnected: text_disconnected };
+            return Bar::new(format, templates, Duration::from_secs(interval.max(1))).run();
         }
-    }
+        CliCommands::Events { json, filter, until, name } => {
+            return EventWatch { json, filters: filter, until, name }.run();
+        }
+    };
+
+    let args: Vec<String> = env::args().skip(1).collect();
+    let mut client = Client::connect()?;
+    let (success, response_message) = client.request(&join_arguments(&args))?;
 
     // Output the parsed response
-    if response_status == "err" {
-        return Err(io::Error::new(io::ErrorKind::Other, response_message));
+    if !success {
+        return Err(io::Error::other(response_message));
+    }
+
+    // Bundles are sent base64-encoded, and written to a file instead of stdout
+    if let Commands::Config { commands: ConfigCommand::Export { name, format: ExportFormat::Bundle, output, .. } } = commands {
+        let bundle = BASE64_STANDARD.decode(response_message.trim())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let output = output.unwrap_or_else(|| format!("{}.bundle.tar.gz", name));
+        fs::write(&output, bundle)?;
+        println!("Bundle written to {}", output);
+        return Ok(());
     }
 
     println!("{}", response_message);
@@ -0,0 +1,232 @@
+use std::sync::Arc;
+use std::time::Duration;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::{error, info};
+use serde_json::json;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::time::interval;
+use common::command::{Cli, Commands, ConfigCommand, ConflictStrategy, SessionCommand};
+use crate::command_handler::CommandHandler;
+use crate::daemon_config::ApiSettings;
+use crate::events::Event;
+use crate::http::{constant_time_eq, Connection, HttpListener, HttpRequest, HttpResponse};
+use crate::response::Response;
+use crate::state::AppState;
+
+const JSON: &str = "application/json";
+
+/// Largest config or archive that can be uploaded.
+const MAX_UPLOAD_SIZE: usize = 16 * 1024 * 1024;
+
+/// Comment lines sent on idle event streams, so proxies and clients don't time them out.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// A REST interface to the socket commands, with the session events as Server-Sent Events.
+pub struct Api {}
+
+impl Api {
+    /// Serves the API on the configured address, until the daemon exits.
+    pub async fn serve(settings: ApiSettings, app_state: Arc<AppState>) {
+        let Some(address) = &settings.listen else { return };
+        let listener = match HttpListener::bind(address, settings.mode).await {
+            Ok(listener) => listener,
+            Err(e) => return error!("Failed to start the API: {}", e),
+        };
+        info!("API listening on {}", address);
+
+        let token = settings.token.map(Arc::new);
+        loop {
+            match listener.accept().await {
+                Ok(stream) => { tokio::spawn(Self::handle_connection(stream, token.clone(), Arc::clone(&app_state))); }
+                Err(e) => error!("Failed to accept an API connection: {}", e),
+            }
+        }
+    }
+
+    async fn handle_connection(mut stream: Box<dyn Connection>, token: Option<Arc<String>>, app_state: Arc<AppState>) {
+        let mut request = match HttpRequest::read_head(stream.as_mut()).await {
+            Ok(request) => request,
+            Err(e) => {
+                _ = Self::error("400 Bad Request", &e.to_string()).send(stream.as_mut()).await;
+                return;
+            }
+        };
+
+        if let Some(token) = token {
+            let authorized = request.header("authorization")
+                .and_then(|value| value.strip_prefix("Bearer "))
+                .is_some_and(|value| constant_time_eq(value.trim().as_bytes(), token.as_bytes()));
+            if !authorized {
+                _ = Self::error("401 Unauthorized", "A valid bearer token is required").send(stream.as_mut()).await;
+                return;
+            }
+        }
+
+        // Only authorized clients may make the daemon read an upload
+        if let Err(e) = request.read_body(stream.as_mut(), MAX_UPLOAD_SIZE).await {
+            _ = Self::error("400 Bad Request", &e.to_string()).send(stream.as_mut()).await;
+            return;
+        }
+
+        info!("API request: {} /{}", request.method, request.path.join("/"));
+        if request.method == "GET" && request.path == ["events"] {
+            if let Err(e) = Self::stream_events(stream.as_mut(), &app_state).await {
+                info!("Event stream closed: {}", e);
+            }
+            return;
+        }
+
+        let response = match Self::route(&request, &app_state).await {
+            Ok(response) => response,
+            Err(e) => Self::error("500 Internal Server Error", &e.to_string()),
+        };
+        if let Err(e) = response.send(stream.as_mut()).await {
+            error!("Failed to send the API response: {}", e);
+        }
+    }
+
+    async fn route(request: &HttpRequest, app_state: &Arc<AppState>) -> Result<HttpResponse> {
+        let path = request.path.iter().map(|segment| segment.as_str()).collect::<Vec<_>>();
+        match (request.method.as_str(), path.as_slice()) {
+            ("GET", ["configs"]) => Self::dispatch(Commands::Config { commands: ConfigCommand::List }, app_state).await,
+            ("POST", ["configs"]) => Self::upload(request, app_state).await,
+            ("GET", ["configs", name]) => Self::get_config(name, app_state).await,
+            ("DELETE", ["configs", name]) => {
+                Self::dispatch(Commands::Config { commands: ConfigCommand::Delete { name: name.to_string() } }, app_state).await
+            }
+            ("GET", ["sessions"]) => Self::get_sessions(app_state).await,
+            ("POST", ["sessions", name, "start"]) => {
+                Self::dispatch(Commands::Session { commands: SessionCommand::Start { name: name.to_string() } }, app_state).await
+            }
+            ("POST", ["sessions", name, "stop"]) => {
+                let active_name = app_state.active_session.read().await.as_ref().map(|session| session.config.name.clone());
+                match active_name {
+                    Some(active_name) if active_name != *name => {
+                        Ok(Self::error("409 Conflict", &format!("The active session belongs to '{}'", active_name)))
+                    }
+                    _ => Self::dispatch(Commands::Session { commands: SessionCommand::Stop }, app_state).await,
+                }
+            }
+            (_, ["configs"] | ["configs", _] | ["sessions"] | ["sessions", _, "start" | "stop"] | ["events"]) => {
+                Ok(Self::error("405 Method Not Allowed", "The method is not supported on this path"))
+            }
+            _ => Ok(Self::error("404 Not Found", "Not found")),
+        }
+    }
+
+    /// Looks the config up in the list the `config list` command returns.
+    async fn get_config(name: &str, app_state: &Arc<AppState>) -> Result<HttpResponse> {
+        let response = Self::handle(Commands::Config { commands: ConfigCommand::List }, app_state).await?;
+        if !response.is_success() {
+            return Ok(Self::from_response(&response));
+        }
+
+        let entries = serde_json::from_str::<Vec<serde_json::Value>>(response.message())?;
+        match entries.into_iter().find(|entry| entry["name"] == name) {
+            Some(entry) => Ok(HttpResponse::new("200 OK", JSON, serde_json::to_string_pretty(&entry)?)),
+            None => Ok(Self::error("404 Not Found", "The specified configuration cannot be found")),
+        }
+    }
+
+    /// Returns the status of the active session as a list, which is empty if there is none.
+    async fn get_sessions(app_state: &Arc<AppState>) -> Result<HttpResponse> {
+        let response = Self::handle(Commands::Session { commands: SessionCommand::Status }, app_state).await?;
+        if !response.is_success() {
+            return Ok(Self::from_response(&response));
+        }
+
+        let sessions = match serde_json::from_str::<serde_json::Value>(response.message()) {
+            Ok(session) if session.is_object() => vec![session],
+            _ => Vec::new(),
+        };
+        Ok(HttpResponse::new("200 OK", JSON, serde_json::to_string_pretty(&sessions)?))
+    }
+
+    /// Imports an uploaded config file or archive. The format follows the Content-Type, and the
+    /// import options are taken from the query string.
+    async fn upload(request: &HttpRequest, app_state: &Arc<AppState>) -> Result<HttpResponse> {
+        let extension = match request.header("content-type").map(|value| value.split(';').next().unwrap_or_default().trim()) {
+            Some("application/zip") => "zip",
+            Some("application/gzip" | "application/x-gzip" | "application/x-tar+gzip") => "tar.gz",
+            Some("application/x-openvpn-profile" | "text/plain") | None => "ovpn",
+            Some(other) => return Ok(Self::error("415 Unsupported Media Type", &format!("Cannot import '{}', expected a config, a .zip or a .tar.gz archive", other))),
+        };
+        let name = request.query("name").map(|name| name.to_string());
+        if extension == "ovpn" && name.is_none() {
+            return Ok(Self::error("400 Bad Request", "The name of the configuration is required"));
+        }
+        let conflict = match request.query("conflict").map(|value| ConflictStrategy::from_str(value, true)).transpose() {
+            Ok(conflict) => conflict,
+            Err(_) => return Ok(Self::error("400 Bad Request", "The conflict strategy must be one of skip, rename or overwrite")),
+        };
+        let flag = |name: &str| request.query(name).is_some_and(|value| matches!(value, "" | "1" | "true"));
+
+        let response = CommandHandler::import_data(&request.body, extension, name, conflict, flag("dry_run"), flag("template"), Arc::clone(app_state)).await?
+            .ok_or_else(|| anyhow!("The command did not return a response"))?;
+        Ok(Self::from_response(&response))
+    }
+
+    /// Handles a command the same way as the socket does, and turns the response into JSON.
+    async fn dispatch(commands: Commands, app_state: &Arc<AppState>) -> Result<HttpResponse> {
+        Ok(Self::from_response(&Self::handle(commands, app_state).await?))
+    }
+
+    async fn handle(commands: Commands, app_state: &Arc<AppState>) -> Result<Response> {
+        CommandHandler::handle_command(Cli { commands }, Arc::clone(app_state)).await?
+            .ok_or_else(|| anyhow!("The command did not return a response"))
+    }
+
+    fn from_response(response: &Response) -> HttpResponse {
+        if !response.is_success() {
+            return Self::error("400 Bad Request", response.message());
+        }
+
+        // Structured replies are already JSON, the others are plain messages
+        let body = match serde_json::from_str::<serde_json::Value>(response.message()) {
+            Ok(value) if value.is_object() || value.is_array() => response.message().to_string(),
+            _ => json!({ "message": response.message() }).to_string(),
+        };
+        HttpResponse::new("200 OK", JSON, body)
+    }
+
+    fn error(status: &'static str, message: &str) -> HttpResponse {
+        HttpResponse::new(status, JSON, json!({ "error": message }).to_string())
+    }
+
+    /// Sends the status of the active session, then every event, until the client disconnects.
+    async fn stream_events(stream: &mut dyn Connection, app_state: &AppState) -> Result<()> {
+        let mut events = app_state.socket_manager.lock().await.subscribe();
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n").await?;
+
+        let current = match app_state.active_session.read().await.as_ref() {
+            Some(session) => Some(Event::Status(Box::new(session.to_serializable().await))),
+            None => None,
+        };
+        if let Some(event) = current {
+            Self::send_event(stream, &event).await?;
+        }
+
+        let mut keepalive = interval(KEEPALIVE_INTERVAL);
+        keepalive.tick().await;
+        loop {
+            tokio::select! {
+                event = events.recv() => match event {
+                    Ok(event) => Self::send_event(stream, &event).await?,
+                    Err(RecvError::Lagged(skipped)) => stream.write_all(format!(": {} events were skipped\n\n", skipped).as_bytes()).await?,
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+                _ = keepalive.tick() => stream.write_all(b": keepalive\n\n").await?,
+            }
+            stream.flush().await?;
+        }
+    }
+
+    async fn send_event(stream: &mut dyn Connection, event: &Event) -> Result<()> {
+        let message = format!("event: {}\ndata: {}\n\n", event.name(), event.to_json()?);
+        stream.write_all(message.as_bytes()).await?;
+        stream.flush().await?;
+        Ok(())
+    }
+}
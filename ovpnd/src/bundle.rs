@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use common::paths::CONFIGS_PATH;
+use crate::config::{ConfigEntry, ConfigManager};
+use crate::importer::Importer;
+
+/// Version of the bundle layout, stored in `metadata.json`.
+const BUNDLE_FORMAT: u32 = 1;
+
+const METADATA_FILE: &str = "metadata.json";
+const CONFIG_FILE: &str = "config.ovpn";
+const FILES_DIR: &str = "files";
+
+/// Directives whose argument is a certificate or key file that can be bundled and inlined.
+const FILE_DIRECTIVES: [&str; 11] = [
+    "ca", "cert", "key", "pkcs12", "tls-auth", "tls-crypt", "tls-crypt-v2", "secret", "dh", "extra-certs", "crl-verify",
+];
+
+/// Directives whose argument is a credentials file; these are only bundled on request.
+const CREDENTIAL_DIRECTIVES: [&str; 1] = ["auth-user-pass"];
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct BundleMetadata {
+    pub format: u32,
+    pub entry: ConfigEntry,
+    pub credentials_included: bool,
+
+    /// Files referenced by the config that are not part of the bundle (missing or credentials).
+    pub omitted_files: Vec<String>,
+}
+
+/// A config read back from a bundle, with all bundled files inlined into the config text.
+pub struct ImportedBundle {
+    pub metadata: BundleMetadata,
+    pub config: String,
+}
+
+pub struct Bundle {}
+
+impl Bundle {
+    /// Creates a `.tar.gz` bundle with the config, the files it references and its index metadata.
+    pub async fn create(entry: &ConfigEntry, include_credentials: bool) -> Result<Vec<u8>> {
+        let text = ConfigManager::get_config_text(entry).await?;
+
+        let mut config = String::new();
+        let mut files: Vec<(String, Vec<u8>)> = Vec::new();
+        let mut omitted_files = Vec::new();
+        for line in text.lines() {
+            let mut tokens = line.split_whitespace();
+            let (Some(directive), Some(path)) = (tokens.next(), tokens.next()) else {
+                config.push_str(line);
+                config.push('\n');
+                continue;
+            };
+
+            let is_credential = CREDENTIAL_DIRECTIVES.contains(&directive);
+            if !FILE_DIRECTIVES.contains(&directive) && !is_credential {
+                config.push_str(line);
+                config.push('\n');
+                continue;
+            }
+
+            let path = path.trim_matches('"');
+            let resolved = Self::resolve_path(path);
+            match (resolved, is_credential && !include_credentials) {
+                (Some(resolved), false) => {
+                    let data = tokio::fs::read(&resolved).await
+                        .with_context(|| format!("Cannot read the referenced file {}", resolved))?;
+                    let file_name = Self::unique_file_name(&files, &resolved);
+                    let rest = tokens.collect::<Vec<_>>().join(" ");
+                    config.push_str(format!("{} {}/{} {}", directive, FILES_DIR, file_name, rest).trim_end());
+                    config.push('\n');
+                    files.push((file_name, data));
+                }
+                _ => {
+                    omitted_files.push(path.to_string());
+                    config.push_str(line);
+                    config.push('\n');
+                }
+            }
+        }
+
+        let metadata = BundleMetadata {
+            format: BUNDLE_FORMAT,
+            entry: entry.clone(),
+            credentials_included: include_credentials,
+            omitted_files,
+        };
+
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        Self::append(&mut builder, METADATA_FILE, serde_json::to_string_pretty(&metadata)?.as_bytes())?;
+        Self::append(&mut builder, CONFIG_FILE, config.as_bytes())?;
+        for (file_name, data) in &files {
+            Self::append(&mut builder, &format!("{}/{}", FILES_DIR, file_name), data)?;
+        }
+
+        Ok(builder.into_inner()?.finish()?)
+    }
+
+    /// Reads a bundle created by [`Bundle::create`]. Returns `None` if the archive is not a bundle.
+    pub fn read(path: &str) -> Result<Option<ImportedBundle>> {
+        let file = std::fs::File::open(path).context("Cannot open the archive")?;
+        let mut archive = tar::Archive::new(GzDecoder::new(file));
+
+        let mut contents = HashMap::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            if !entry.header().entry_type().is_file() {
+                continue;
+            }
+
+            let entry_path = entry.path()?.to_string_lossy().trim_start_matches("./").to_string();
+            let data = Importer::read_limited(&mut entry).with_context(|| format!("Cannot read {}", entry_path))?;
+            contents.insert(entry_path, data);
+        }
+
+        let Some(metadata) = contents.get(METADATA_FILE) else {
+            return Ok(None);
+        };
+        let metadata: BundleMetadata = serde_json::from_slice(metadata).context("Invalid bundle metadata")?;
+        if metadata.format > BUNDLE_FORMAT {
+            return Err(anyhow!("The bundle uses format version {}, which is newer than this version of ovpnd supports", metadata.format));
+        }
+
+        let text = contents.get(CONFIG_FILE).context("The bundle does not contain a configuration")?;
+        let text = String::from_utf8_lossy(text);
+
+        let mut config = String::new();
+        for line in text.lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            let bundled = match tokens.as_slice() {
+                [directive, path, ..] if path.starts_with(&format!("{}/", FILES_DIR)) => contents.get(*path).map(|d| (*directive, d)),
+                _ => None,
+            };
+
+            match bundled {
+                Some((directive, data)) => {
+                    // Inline the bundled file, so the stored config stays a single self-contained file
+                    if matches!(directive, "tls-auth" | "secret") && tokens.len() > 2 {
+                        config.push_str(&format!("key-direction {}\n", tokens[2]));
+                    }
+                    let data = match directive {
+                        "pkcs12" => BASE64_STANDARD.encode(data),
+                        _ => String::from_utf8_lossy(data).to_string(),
+                    };
+                    config.push_str(&format!("<{}>\n{}\n</{}>\n", directive, data.trim_end(), directive));
+                }
+                None => {
+                    config.push_str(line);
+                    config.push('\n');
+                }
+            }
+        }
+
+        Ok(Some(ImportedBundle { metadata, config }))
+    }
+
+    fn append<W: std::io::Write>(builder: &mut tar::Builder<W>, path: &str, data: &[u8]) -> Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(data.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(chrono::Utc::now().timestamp() as u64);
+        header.set_cksum();
+        builder.append_data(&mut header, path, data)?;
+        Ok(())
+    }
+
+    /// Resolves a file referenced by a config, relative paths are tried against the configs directory.
+    fn resolve_path(path: &str) -> Option<String> {
+        let candidates = [path.to_string(), format!("{}/{}", CONFIGS_PATH, path)];
+        candidates.into_iter().find(|p| Path::new(p).is_file())
+    }
+
+    fn unique_file_name(files: &[(String, Vec<u8>)], path: &str) -> String {
+        let base = Path::new(path).file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+        let mut candidate = base.clone();
+        let mut counter = 1;
+        while files.iter().any(|(name, _)| *name == candidate) {
+            counter += 1;
+            candidate = format!("{}-{}", counter, base);
+        }
+
+        candidate
+    }
+}
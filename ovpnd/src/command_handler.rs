@@ -1,15 +1,37 @@
-use std::path::Path;
 use std::sync::Arc;
-use common::command::{Cli, Commands, ConfigCommand, SessionCommand};
-use crate::config::ConfigManager;
+use std::time::Instant;
+use log::error;
+use base64::prelude::{Engine, BASE64_STANDARD};
+use tokio::fs;
+use uuid::Uuid;
+use common::command::{Cli, Commands, ConfigCommand, ConflictStrategy, DaemonCommand, ExportFormat, SessionCommand};
+use common::paths::RUNTIME_PATH;
+use crate::bundle::Bundle;
+use crate::config::{ConfigEntry, ConfigKind, ConfigManager, ConsistencyReport};
+use crate::history::History;
+use crate::importer::{ImportAction, Importer};
+use crate::kill_switch::KillSwitch;
+use crate::metrics::Metrics;
 use crate::response::Response;
 use crate::state::AppState;
 
 pub struct CommandHandler {}
 
 impl CommandHandler {
+    /// Handles a command of any of the interfaces, and records its result and duration.
     pub async fn handle_command(command: Cli, app_state: Arc<AppState>) -> anyhow::Result<Option<Response>> {
-        return match command.commands {
+        let received = Instant::now();
+        let command_name = Metrics::command_name(&command.commands);
+        let response = Self::execute(command, Arc::clone(&app_state)).await;
+
+        let success = response.as_ref().is_ok_and(|response| response.as_ref().is_none_or(|response| response.is_success()));
+        app_state.metrics.record_command(command_name, success, received.elapsed());
+
+        response
+    }
+
+    async fn execute(command: Cli, app_state: Arc<AppState>) -> anyhow::Result<Option<Response>> {
+        match command.commands {
             Commands::Config { commands } => match commands {
                 ConfigCommand::List => {
                     let config_manager = app_state.config_manager.read().await;
@@ -17,21 +39,56 @@ impl CommandHandler {
                     let entries = config_index.get_entries();
                     Ok(Some(Response::success(serde_json::to_string_pretty(&entries)?)))
                 }
-                ConfigCommand::Import { path, name } => {
-                    let name = match name {
-                        Some(name) => name,
-                        None => Path::new(path.as_str()).file_stem().unwrap().to_str().unwrap().to_string(),
+                ConfigCommand::Import { path, name, conflict, dry_run, template } => {
+                    let bulk = Importer::is_bulk(path.as_str());
+                    let sources = match Importer::read_sources(path.as_str()).await {
+                        Ok(sources) => sources,
+                        Err(e) => return Ok(Some(Response::fail(format!("Failed to import configuration: {}", e)))),
                     };
 
-                    match app_state.config_manager.write().await.import(path, name).await {
-                        Ok(_) => Ok(Some(Response::success("Configuration imported successfully".to_string()))),
-                        Err(e) => Ok(Some(Response::fail(format!("Failed to import configuration: {}", e))))
+                    let names = sources.iter().map(|source| match (&name, bulk) {
+                        (Some(name), false) => name.clone(),
+                        (Some(prefix), true) => format!("{}-{}", prefix, source.stem),
+                        (None, _) => source.stem.clone(),
+                    }).collect();
+
+                    let daemon_config = app_state.daemon_config.read().await.clone();
+                    let mut config_manager = app_state.config_manager.write().await;
+                    let report = Importer::import(&mut config_manager, &daemon_config, sources, names, conflict, dry_run).await;
+                    if template && !dry_run {
+                        for result in report.results.iter().filter(|r| r.error.is_none() && r.action != ImportAction::Skipped) {
+                            config_manager.update(&result.name, |entry| entry.kind = ConfigKind::Template).await?;
+                        }
+                    }
+                    drop(config_manager);
+                    if !dry_run {
+                        for result in report.results.iter().filter(|r| r.error.is_none() && r.action != ImportAction::Skipped) {
+                            let entry = app_state.config_manager.read().await.get_by_name(result.name.clone()).await.ok();
+                            Self::notify_config_change(&app_state, format!("Configuration '{}' imported", result.name), entry.as_deref()).await;
+                        }
+                    }
+                    if bulk || dry_run {
+                        return Ok(Some(Response::success(serde_json::to_string_pretty(&report)?)));
+                    }
+
+                    let result = &report.results[0];
+                    match (&result.action, &result.error) {
+                        (ImportAction::Skipped, _) => Ok(Some(Response::success("A configuration with the same name already exists, skipped".to_string()))),
+                        (_, Some(e)) => Ok(Some(Response::fail(format!("Failed to import configuration: {}", e)))),
+                        (_, None) if result.warnings.is_empty() => Ok(Some(Response::success(format!("Configuration imported successfully as '{}'", result.name)))),
+                        (_, None) => Ok(Some(Response::success(format!("Configuration imported successfully as '{}', with warnings:\n{}", result.name, result.warnings.join("\n"))))),
                     }
                 },
-                ConfigCommand::Export { name } => {
+                ConfigCommand::Export { name, format, include_credentials, .. } => {
                     match app_state.config_manager.read().await.get_by_name(name).await {
                         Ok(config) => {
-                            match ConfigManager::get_config_text(config.as_ref()).await {
+                            let exported = match format {
+                                ExportFormat::Config => ConfigManager::get_config_text(config.as_ref()).await,
+                                ExportFormat::Bundle => Bundle::create(config.as_ref(), include_credentials).await
+                                    .map(|bundle| BASE64_STANDARD.encode(bundle)),
+                            };
+
+                            match exported {
                                 Ok(text) => Ok(Some(Response::success(text.to_string()))),
                                 Err(e) => Ok(Some(Response::fail(format!("Failed to export configuration: {}", e))))
                             }
@@ -39,26 +96,97 @@ impl CommandHandler {
                         Err(_) => Ok(Some(Response::fail("The specified configuration cannot be found".to_string())))
                     }
                 },
+                ConfigCommand::Profile { name, template, variables } => {
+                    let variables = variables.into_iter().collect();
+                    match app_state.config_manager.write().await.set_profile(name, template, variables).await {
+                        Ok(entry) => {
+                            Self::notify_config_change(&app_state, format!("Profile '{}' saved", entry.name), Some(entry.as_ref())).await;
+                            Ok(Some(Response::success(format!("Profile '{}' saved successfully", entry.name))))
+                        }
+                        Err(e) => Ok(Some(Response::fail(format!("Failed to save profile: {}", e)))),
+                    }
+                },
+                ConfigCommand::Set { name, arguments, environment, clear_args, clear_env, route_mode, routes, clear_routes, kill_switch, hooks, clear_hooks, autostart } => {
+                    let daemon_config = app_state.daemon_config.read().await.clone();
+                    let mut config_manager = app_state.config_manager.write().await;
+                    let Some(mut entry) = config_manager.get_index().get_entries().iter().find(|e| e.name == name).cloned() else {
+                        return Ok(Some(Response::fail("The specified configuration cannot be found".to_string())));
+                    };
+
+                    if clear_args {
+                        entry.arguments.clear();
+                    }
+                    if clear_env {
+                        entry.environment.clear();
+                    }
+                    if clear_routes {
+                        entry.routes.targets.clear();
+                    }
+                    if clear_hooks {
+                        entry.hooks.clear();
+                    }
+                    entry.arguments.extend(arguments);
+                    entry.environment.extend(environment);
+                    entry.routes.mode = route_mode.unwrap_or(entry.routes.mode);
+                    entry.routes.targets.extend(routes);
+                    entry.kill_switch = kill_switch.unwrap_or(entry.kill_switch);
+                    entry.hooks.extend(hooks);
+                    entry.autostart = autostart.unwrap_or(entry.autostart);
+
+                    if let Err(e) = entry.validate(&daemon_config) {
+                        return Ok(Some(Response::fail(format!("Failed to update configuration: {}", e))));
+                    }
+
+                    match config_manager.update(&name, |existing| *existing = entry).await {
+                        Ok(entry) => {
+                            drop(config_manager);
+                            Self::notify_config_change(&app_state, format!("Configuration '{}' updated", name), Some(entry.as_ref())).await;
+                            Ok(Some(Response::success("Configuration updated successfully".to_string())))
+                        }
+                        Err(e) => Ok(Some(Response::fail(format!("Failed to update configuration: {}", e)))),
+                    }
+                },
                 ConfigCommand::Delete { name } => {
-                    match app_state.config_manager.write().await.delete(name).await {
-                        Ok(_) => Ok(Some(Response::success("Configuration deleted successfully".to_string()))),
+                    let mut config_manager = app_state.config_manager.write().await;
+                    let entry = config_manager.get_index().get_entries().iter().find(|e| e.name == name).cloned();
+                    let result = config_manager.delete(name.clone()).await;
+                    drop(config_manager);
+                    match result {
+                        Ok(_) => {
+                            Self::notify_config_change(&app_state, format!("Configuration '{}' deleted", name), entry.as_ref()).await;
+                            Ok(Some(Response::success("Configuration deleted successfully".to_string())))
+                        }
                         Err(e) => Ok(Some(Response::fail(format!("Failed to delete configuration: {}", e)))),
                     }
                 },
+                ConfigCommand::Fsck { repair } => {
+                    let report = match repair {
+                        true => app_state.config_manager.write().await.repair().await,
+                        false => app_state.config_manager.read().await.check().await
+                            .map(|issues| ConsistencyReport { issues, repairs: Vec::new() }),
+                    };
+
+                    match report {
+                        Ok(report) => Ok(Some(Response::success(serde_json::to_string_pretty(&report)?))),
+                        Err(e) => Ok(Some(Response::fail(format!("Failed to check the configuration store: {}", e)))),
+                    }
+                },
             },
             Commands::Session { commands } => match commands {
                 SessionCommand::Start { name } => {
-                    if let Some(_) = app_state.active_session.read().await.as_ref() {
+                    if app_state.has_active_session().await {
                         return Ok(Some(Response::fail("Cannot start new session because another one is already active".to_string())));
                     }
 
-                    match app_state.config_manager.read().await.get_by_name(name).await {
+                    // Starting the session updates the index, so the lock must not be held
+                    let config_entry = app_state.config_manager.read().await.get_by_name(name).await;
+                    match config_entry {
                         Ok(config_entry) => {
                             match app_state.session_manager.start(config_entry, app_state.clone()).await {
                                 Ok(_) => {
                                     Ok(Some(Response::success("Session started successfully".to_string())))
                                 }
-                                Err(_) => Ok(Some(Response::fail("Failed to start the session".to_string()))),
+                                Err(e) => Ok(Some(Response::fail(format!("Failed to start the session: {}", e)))),
                             }
                         }
                         Err(_) => Ok(Some(Response::fail("The specified configuration cannot be found".to_string())))
@@ -71,7 +199,12 @@ impl CommandHandler {
                             Err(_) => Ok(Some(Response::fail("Failed to stop the session".to_string())))
                         }
                     } else {
-                        Ok(Some(Response::fail("No session is currently active".to_string())))
+                        // The kill switch outlives sessions that exit on their own
+                        match KillSwitch::remove().await {
+                            Ok(true) => Ok(Some(Response::success("No session is active, the kill switch was removed".to_string()))),
+                            Ok(false) => Ok(Some(Response::fail("No session is currently active".to_string()))),
+                            Err(e) => Ok(Some(Response::fail(format!("No session is active, but the kill switch could not be removed: {}", e)))),
+                        }
                     }
                 }
                 SessionCommand::Status => {
@@ -81,7 +214,53 @@ impl CommandHandler {
                         None => Ok(Some(Response::success("No active sessions".to_string())))
                     }
                 }
+                SessionCommand::History { name, since, limit } => {
+                    let since = match since.as_deref().map(History::parse_since).transpose() {
+                        Ok(since) => since,
+                        Err(e) => return Ok(Some(Response::fail(e.to_string()))),
+                    };
+                    // Sessions of a renamed config are still found through its guid
+                    let guid = match &name {
+                        Some(name) => app_state.config_manager.read().await.get_by_name(name.clone()).await.ok().map(|entry| entry.guid),
+                        None => None,
+                    };
+
+                    match History::query(name.as_deref(), guid.as_deref(), since, limit).await {
+                        Ok(records) => Ok(Some(Response::success(serde_json::to_string_pretty(&records)?))),
+                        Err(e) => Ok(Some(Response::fail(format!("Failed to read the session history: {}", e)))),
+                    }
+                }
+            }
+            Commands::Daemon { commands } => match commands {
+                DaemonCommand::Reload => match app_state.reload().await {
+                    Ok(report) => Ok(Some(Response::success(serde_json::to_string_pretty(&report)?))),
+                    Err(e) => Ok(Some(Response::fail(format!("Failed to reload the configuration, keeping the current one: {}", e)))),
+                },
             }
-        };
+        }
+    }
+
+    /// Tells the clients that the config store changed, as a `config` daemon event.
+    async fn notify_config_change(app_state: &AppState, message: String, entry: Option<&ConfigEntry>) {
+        if let Err(e) = app_state.socket_manager.lock().await.broadcast_daemon_event("config", &message, entry).await {
+            error!("Failed to broadcast the config event: {}", e);
+        }
+    }
+
+    /// Imports a config or an archive received from a client instead of read from a path. The
+    /// importer works on files, so the data is stored until the import is done. `extension` is
+    /// `ovpn`, `zip` or `tar.gz`.
+    pub async fn import_data(data: &[u8], extension: &str, name: Option<String>, conflict: Option<ConflictStrategy>, dry_run: bool, template: bool, app_state: Arc<AppState>) -> anyhow::Result<Option<Response>> {
+        let directory = format!("{}/uploads/{}", RUNTIME_PATH, Uuid::new_v4());
+        let path = format!("{}/upload.{}", directory, extension);
+        fs::create_dir_all(&directory).await?;
+        let result = async {
+            fs::write(&path, data).await?;
+            let command = ConfigCommand::Import { name, path: path.clone(), conflict, dry_run, template };
+            Self::handle_command(Cli { commands: Commands::Config { commands: command } }, app_state).await
+        }.await;
+        _ = fs::remove_dir_all(&directory).await;
+
+        result
     }
 }
\ No newline at end of file
@@ -1,49 +1,251 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::Permissions;
+use std::io::ErrorKind;
+use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use serde::{Deserialize, Serialize};
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use serde_json::Value;
 use tokio::fs;
 use uuid::Uuid;
-use common::paths::CONFIGS_PATH;
+use common::paths::RUNTIME_PATH;
+use common::arguments::split_arguments;
+use common::command::RouteMode;
+use crate::daemon_config::DaemonConfig;
+use crate::routes::RoutePolicy;
+use crate::template::Template;
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A single schema upgrade step, transforming a raw index document from version `N` to `N + 1`.
+type Migration = fn(&mut Value) -> Result<()>;
+
+/// Schema upgrade steps, where the element at position `N` upgrades an index from version `N` to `N + 1`.
+/// New migrations must only ever be appended to the end of this list.
+const MIGRATIONS: &[Migration] = &[
+    migrate_v0_to_v1,
+    add_fields_with_defaults, // v2: template profiles
+    add_fields_with_defaults, // v3: extra openvpn arguments and environment
+    add_fields_with_defaults, // v4: split-tunnel route policies
+    add_fields_with_defaults, // v5: kill switch
+    add_fields_with_defaults, // v6: lifecycle hooks
+    add_fields_with_defaults, // v7: last use and autostart
+];
+
+/// The schema version written by this build of the daemon.
+pub const INDEX_VERSION: u64 = MIGRATIONS.len() as u64;
+
+/// Version 0 indexes were a bare `{"entries": [...]}` document without a version field.
+fn migrate_v0_to_v1(index: &mut Value) -> Result<()> {
+    let index = index.as_object_mut().context("The index root is not an object")?;
+    index.entry("entries").or_insert_with(|| Value::Array(Vec::new()));
+    Ok(())
+}
+
+/// Used by versions that only added entry fields with default values. The version bump itself
+/// is what matters: it stops older daemons from loading the index and dropping the new fields.
+fn add_fields_with_defaults(_index: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub enum ConfigKind {
+    /// A regular config, stored as `<guid>.conf`.
+    #[default]
+    Config,
+    /// A config containing `{{var}}` placeholders, which cannot be started directly.
+    Template,
+    /// A lightweight entry without a file of its own, rendered from a template when started.
+    Profile,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct ConfigEntry {
     pub name: String,
     pub guid: String,
+
+    #[serde(default)]
+    pub kind: ConfigKind,
+
+    /// GUID of the template a profile is rendered from.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
+
+    /// Values of the template variables of a profile.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub variables: BTreeMap<String, String>,
+
+    /// Extra openvpn command-line arguments, one `--directive` with its parameters per element.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arguments: Vec<String>,
+
+    /// Environment variables set for the openvpn process.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub environment: BTreeMap<String, String>,
+
+    #[serde(default)]
+    pub routes: RoutePolicy,
+
+    /// Blocks all traffic outside the tunnel from the session start until an explicit stop.
+    #[serde(default)]
+    pub kill_switch: bool,
+
+    /// Executables run on session status changes, after the ones in the hooks directory.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub hooks: Vec<String>,
+
+    /// When a session was last started with this config.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_used: Option<DateTime<Utc>>,
+
+    /// Started by the daemon at startup if the startup policy is `autostart`.
+    #[serde(default)]
+    pub autostart: bool,
+}
+
+impl ConfigEntry {
+    /// Checks the per-config settings against the daemon configuration.
+    pub fn validate(&self, daemon_config: &DaemonConfig) -> Result<()> {
+        let policy = &daemon_config.policy;
+        for argument in &self.arguments {
+            let tokens = split_arguments(argument).map_err(|e| anyhow!("Invalid argument '{}': {}", argument, e))?;
+            if !tokens.first().is_some_and(|t| t.starts_with("--")) {
+                return Err(anyhow!("Invalid argument '{}': arguments must start with a --directive", argument));
+            }
+            policy.check_arguments(&tokens)?;
+        }
+        policy.check_environment(self.environment.keys())?;
+        self.routes.validate()?;
+        if self.kill_switch && self.routes.mode == RouteMode::Exclude {
+            return Err(anyhow!("The kill switch cannot be combined with the 'exclude' route mode, excluded routes would be blocked"));
+        }
+        if !self.hooks.is_empty() && !daemon_config.hooks.allow_config_hooks {
+            return Err(anyhow!("Per-config hooks are not allowed by the daemon configuration"));
+        }
+        if let Some(hook) = self.hooks.iter().find(|hook| !Path::new(hook).is_absolute()) {
+            return Err(anyhow!("Invalid hook '{}', expected an absolute path", hook));
+        }
+
+        Ok(())
+    }
+
+    /// Returns the extra openvpn arguments as separate tokens.
+    pub fn get_argument_tokens(&self) -> Result<Vec<String>> {
+        let mut tokens = Vec::new();
+        for argument in &self.arguments {
+            tokens.extend(split_arguments(argument).map_err(|e| anyhow!("Invalid argument '{}': {}", argument, e))?);
+        }
+
+        Ok(tokens)
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ConfigIndex {
+    #[serde(default)]
+    version: u64,
     entries: Vec<ConfigEntry>,
 }
 
 impl ConfigIndex {
     pub async fn load(index_path: &str) -> Result<Self> {
-        if Path::new(index_path).exists() {
-            let data = fs::read_to_string(index_path).await?;
-            let index: ConfigIndex = serde_json::from_str(&data)?;
-            Ok(index)
-        } else {
-            Ok(ConfigIndex {
+        if !Path::new(index_path).exists() {
+            return Ok(ConfigIndex {
+                version: INDEX_VERSION,
                 entries: Vec::new(),
-            })
+            });
+        }
+
+        let data = fs::read_to_string(index_path).await?;
+        let mut document: Value = serde_json::from_str(&data)
+            .context("The configuration index is not valid JSON")?;
+        let version = document.get("version").and_then(Value::as_u64).unwrap_or(0);
+
+        if version > INDEX_VERSION {
+            return Err(anyhow!("The configuration index '{}' uses schema version {}, but this version of ovpnd only supports up to version {}. Please upgrade ovpnd.",
+                index_path, version, INDEX_VERSION));
+        }
+
+        if version == INDEX_VERSION {
+            return Ok(serde_json::from_value(document)?);
         }
+
+        let backup_path = format!("{}.v{}.bak", index_path, version);
+        fs::copy(index_path, &backup_path).await
+            .context("Failed to back up the configuration index before migrating it")?;
+        fs::set_permissions(&backup_path, Permissions::from_mode(FILE_MODE)).await?;
+        info!("Backed up configuration index to {}", backup_path);
+
+        for (from_version, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            migration(&mut document)
+                .with_context(|| format!("Failed to migrate the configuration index from version {}", from_version))?;
+            document["version"] = Value::from(from_version as u64 + 1);
+        }
+
+        let index: ConfigIndex = serde_json::from_value(document)?;
+        index.save(index_path).await?;
+        info!("Migrated configuration index from version {} to version {}", version, INDEX_VERSION);
+
+        Ok(index)
     }
 
     pub async fn save(&self, index_path: &str) -> Result<()> {
         let data = serde_json::to_string_pretty(&self)?;
         fs::write(index_path, data).await?;
+        fs::set_permissions(index_path, Permissions::from_mode(FILE_MODE)).await?;
         Ok(())
     }
 
     pub fn get_entries(&self) -> &Vec<ConfigEntry> {
-        return &self.entries;
+        &self.entries
     }
 
     pub fn get_entries_mut(&mut self) -> &mut Vec<ConfigEntry> {
-        return &mut self.entries;
+        &mut self.entries
     }
 }
 
+/// Directory of the config store.
+#[cfg(not(test))]
+fn store_path() -> String {
+    common::paths::CONFIGS_PATH.to_string()
+}
+
+/// Every test thread uses a temporary config store of its own.
+#[cfg(test)]
+fn store_path() -> String {
+    tests::STORE_PATH.with(|path| path.borrow().clone())
+}
+
+/// Expected permissions of the configs directory; configurations may contain private keys.
+const DIRECTORY_MODE: u32 = 0o700;
+
+/// Expected permissions of the index and of every stored configuration file.
+const FILE_MODE: u32 = 0o600;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "problem")]
+pub enum ConsistencyIssue {
+    /// An index entry whose `<guid>.conf` file does not exist.
+    MissingFile { name: String, guid: String },
+    /// A profile whose template is not in the index.
+    MissingTemplate { name: String, template: String },
+    /// A `<guid>.conf` file that is not referenced by any index entry.
+    OrphanedFile { guid: String },
+    /// More than one index entry uses the same name.
+    DuplicateName { name: String },
+    /// More than one index entry points to the same file.
+    DuplicateGuid { guid: String },
+    /// A file or directory in the store is accessible to other users.
+    WrongPermissions { path: String, mode: String, expected: String },
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct ConsistencyReport {
+    pub issues: Vec<ConsistencyIssue>,
+    pub repairs: Vec<String>,
+}
+
 pub struct ConfigManager {
     index: ConfigIndex,
 }
@@ -53,38 +255,118 @@ impl ConfigManager {
         let index_path = Self::get_index_path().await?;
         let index = ConfigIndex::load(index_path.as_str()).await?;
 
-        return Ok(ConfigManager { index });
+        Ok(ConfigManager { index })
     }
 
-    pub async fn import(&mut self, path: String, name: String) -> Result<Box<ConfigEntry>> {
-        if let Some(_) = self.index.entries.iter().find(|e| e.name == name) {
-            return Err(anyhow!("A configuration with the same name already exists."));
-        }
+    /// Writes `data` as the configuration called `name`. If a configuration with that name
+    /// already exists its contents are replaced, otherwise a new entry is created.
+    pub async fn store(&mut self, data: &[u8], name: String) -> Result<Box<ConfigEntry>> {
+        let existing = self.index.entries.iter().find(|e| e.name == name).cloned();
+        let entry = match existing {
+            // A profile that gets overwritten with real contents becomes a regular config
+            Some(entry) if entry.kind == ConfigKind::Profile => ConfigEntry {
+                kind: ConfigKind::Config,
+                template: None,
+                variables: BTreeMap::new(),
+                ..entry
+            },
+            Some(entry) => entry,
+            None => ConfigEntry {
+                guid: Uuid::new_v4().to_string(),
+                name,
+                ..Default::default()
+            },
+        };
+
+        let config_path = Self::get_config_path_and_check(&entry).await?;
+        fs::write(config_path.as_ref(), data).await?;
+        fs::set_permissions(config_path.as_ref(), Permissions::from_mode(FILE_MODE)).await?;
+
+        self.put_entry(entry.clone());
+
+        let index_path = Self::get_index_path().await?;
+        self.index.save(index_path.as_str()).await?;
 
-        let guid = Uuid::new_v4().to_string();
+        Ok(Box::new(entry))
+    }
 
+    /// Replaces the entry with the same GUID, or appends it if it is new.
+    fn put_entry(&mut self, entry: ConfigEntry) {
         let entries = self.index.get_entries_mut();
-        let entry = ConfigEntry {
-            guid,
-            name,
+        match entries.iter_mut().find(|e| e.guid == entry.guid) {
+            Some(existing) => *existing = entry,
+            None => entries.push(entry),
+        }
+    }
+
+    pub fn has_name(&self, name: &str) -> bool {
+        self.index.entries.iter().any(|e| e.name == name)
+    }
+
+    /// Applies `change` to the configuration called `name` and persists the index.
+    pub async fn update<F>(&mut self, name: &str, change: F) -> Result<Box<ConfigEntry>>
+    where
+        F: FnOnce(&mut ConfigEntry),
+    {
+        let entry = self.index.entries.iter_mut().find(|e| e.name == name)
+            .context("The specified configuration cannot be found.")?;
+        change(entry);
+        let entry = entry.clone();
+
+        let index_path = Self::get_index_path().await?;
+        self.index.save(index_path.as_str()).await?;
+
+        Ok(Box::new(entry))
+    }
+
+    /// Creates a profile of the template called `template_name`, or updates the variables of an
+    /// existing profile. Every variable used by the template must have a value.
+    pub async fn set_profile(&mut self, name: String, template_name: String, variables: BTreeMap<String, String>) -> Result<Box<ConfigEntry>> {
+        let template = self.index.entries.iter().find(|e| e.name == template_name && e.kind == ConfigKind::Template)
+            .context("The specified template cannot be found.")?;
+        let template_text = Self::get_config_text(template).await?;
+        let template_guid = template.guid.clone();
+
+        let mut entry = match self.index.entries.iter().find(|e| e.name == name) {
+            Some(entry) if entry.kind == ConfigKind::Profile => entry.clone(),
+            Some(_) => return Err(anyhow!("A configuration with the same name already exists.")),
+            None => ConfigEntry {
+                name,
+                guid: Uuid::new_v4().to_string(),
+                kind: ConfigKind::Profile,
+                ..Default::default()
+            },
         };
-        let entry_clone = entry.clone();
-        let config_path = Self::get_config_path_and_check(&entry_clone).await?;
-        fs::copy(path, config_path.as_ref()).await?;
+        entry.template = Some(template_guid);
+        entry.variables.extend(variables);
+        Template::render(&template_text, &entry.variables)?;
 
-        entries.push(entry.clone());
+        self.put_entry(entry.clone());
 
         let index_path = Self::get_index_path().await?;
         self.index.save(index_path.as_str()).await?;
 
-        return Ok(Box::new(entry));
+        Ok(Box::new(entry))
     }
 
     pub async fn delete(&mut self, config_name: String) -> Result<()> {
         match self.index.entries.iter().find(|e| e.name == config_name) {
             Some(entry) => {
-                let config_path = Self::get_config_path_and_check(&entry).await?;
-                fs::remove_file(config_path.as_ref()).await?;
+                let profiles = self.index.entries.iter()
+                    .filter(|e| e.template.as_ref() == Some(&entry.guid))
+                    .map(|e| e.name.as_str())
+                    .collect::<Vec<_>>();
+                if !profiles.is_empty() {
+                    return Err(anyhow!("The template is still used by the following profiles: {}", profiles.join(", ")));
+                }
+
+                match entry.kind {
+                    ConfigKind::Profile => Self::remove_rendered_config(entry).await?,
+                    _ => {
+                        let config_path = Self::get_config_path_and_check(entry).await?;
+                        fs::remove_file(config_path.as_ref()).await?;
+                    }
+                }
 
                 let entries = self.index.get_entries_mut();
                 entries.retain(|e| e.name != config_name);
@@ -94,57 +376,515 @@ impl ConfigManager {
 
                 Ok(())
             },
-            None => return Err(anyhow!("The specified configuration cannot be found."))
+            None => Err(anyhow!("The specified configuration cannot be found."))
         }
     }
 
     pub async fn get_by_name(&self, config_name: String) -> Result<Box<ConfigEntry>> {
-        return match self.index.entries.iter().find(|e| e.name == config_name) {
+        match self.index.entries.iter().find(|e| e.name == config_name) {
             Some(entry) => {
-                let config_path = Self::get_config_path_and_check(&entry).await?;
+                Self::get_config_path_and_check(entry).await?;
 
-                match Path::new(&config_path.as_ref()).exists() {
+                match Path::new(&Self::get_source_path(entry)).exists() {
                     true => Ok(Box::new(entry.clone())),
-                    false => return Err(anyhow!("The specified configuration cannot be found."))
+                    false => Err(anyhow!("The specified configuration cannot be found."))
                 }
             },
             None => Err(anyhow!("The specified configuration cannot be found."))
-        };
+        }
     }
 
     pub async fn get_config_path_and_check(entry: &ConfigEntry) -> Result<Box<String>> {
         let config_path = Self::get_config_path(entry);
 
-        if !Path::new(&CONFIGS_PATH).exists() {
-            fs::create_dir_all(&CONFIGS_PATH).await?;
+        if !Path::new(&store_path()).exists() {
+            fs::create_dir_all(&store_path()).await?;
         }
 
-        return Ok(Box::new(config_path));
+        Ok(Box::new(config_path))
     }
 
     pub fn get_config_path(entry: &ConfigEntry) -> String {
-        return format!("{}/{}.conf", CONFIGS_PATH, entry.guid);
+        format!("{}/{}.conf", store_path(), entry.guid)
+    }
+
+    /// Returns the stored file the config is read from, which is the template's file for profiles.
+    pub fn get_source_path(entry: &ConfigEntry) -> String {
+        match (&entry.kind, &entry.template) {
+            (ConfigKind::Profile, Some(template)) => format!("{}/{}.conf", store_path(), template),
+            _ => Self::get_config_path(entry),
+        }
     }
 
+    /// Returns the config text, rendering the template of profiles.
     pub async fn get_config_text(entry: &ConfigEntry) -> Result<String> {
-        let config_path = Self::get_config_path(entry);
-        match fs::read_to_string(config_path).await {
-            Ok(text) => Ok(text),
-            Err(_) => Err(anyhow!("Cannot read the configuration file."))
+        let text = match fs::read_to_string(Self::get_source_path(entry)).await {
+            Ok(text) => text,
+            Err(_) => return Err(anyhow!("Cannot read the configuration file."))
+        };
+
+        match entry.kind {
+            ConfigKind::Profile => Template::render(&text, &entry.variables),
+            _ => Ok(text),
+        }
+    }
+
+    /// Returns the path of a file openvpn can be started with. Profiles are rendered into the
+    /// runtime directory first, with all template variables validated.
+    pub async fn prepare_config_path(entry: &ConfigEntry) -> Result<String> {
+        match entry.kind {
+            ConfigKind::Config => Ok(Self::get_config_path(entry)),
+            ConfigKind::Template => Err(anyhow!("Templates cannot be started directly, create a profile from it first.")),
+            ConfigKind::Profile => {
+                let text = Self::get_config_text(entry).await?;
+                fs::create_dir_all(RUNTIME_PATH).await?;
+                fs::set_permissions(RUNTIME_PATH, Permissions::from_mode(DIRECTORY_MODE)).await?;
+
+                let rendered_path = Self::get_rendered_path(entry);
+                fs::write(&rendered_path, text).await?;
+                fs::set_permissions(&rendered_path, Permissions::from_mode(FILE_MODE)).await?;
+                Ok(rendered_path)
+            }
+        }
+    }
+
+    /// Removes the config rendered for a profile by [`ConfigManager::prepare_config_path`], if any.
+    pub async fn remove_rendered_config(entry: &ConfigEntry) -> Result<()> {
+        if entry.kind != ConfigKind::Profile {
+            return Ok(());
+        }
+
+        match fs::remove_file(Self::get_rendered_path(entry)).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
         }
     }
 
+    fn get_rendered_path(entry: &ConfigEntry) -> String {
+        format!("{}/{}.conf", RUNTIME_PATH, entry.guid)
+    }
+
     async fn get_index_path() -> Result<String> {
-        let index_path = format!("{}/{}", CONFIGS_PATH, "index.json");
+        let index_path = format!("{}/{}", store_path(), "index.json");
 
         if !Path::new(&index_path).exists() {
-            fs::create_dir_all(CONFIGS_PATH).await?;
+            fs::create_dir_all(store_path()).await?;
         }
 
-        return Ok(index_path);
+        Ok(index_path)
     }
 
     pub fn get_index(&self) -> &ConfigIndex {
-        return &self.index;
+        &self.index
+    }
+
+    /// Compares the index with the contents of the configs directory without changing anything.
+    pub async fn check(&self) -> Result<Vec<ConsistencyIssue>> {
+        let mut issues = Vec::new();
+        let entries = self.index.get_entries();
+
+        let mut seen_guids = HashSet::new();
+        let mut seen_names = HashSet::new();
+        for entry in entries {
+            if !seen_guids.insert(entry.guid.as_str()) {
+                issues.push(ConsistencyIssue::DuplicateGuid { guid: entry.guid.clone() });
+            }
+            if !seen_names.insert(entry.name.as_str()) {
+                issues.push(ConsistencyIssue::DuplicateName { name: entry.name.clone() });
+            }
+            if entry.kind == ConfigKind::Profile {
+                let template = entry.template.clone().unwrap_or_default();
+                if !entries.iter().any(|e| e.guid == template && e.kind == ConfigKind::Template) {
+                    issues.push(ConsistencyIssue::MissingTemplate { name: entry.name.clone(), template });
+                }
+            } else if !Path::new(&Self::get_config_path(entry)).exists() {
+                issues.push(ConsistencyIssue::MissingFile { name: entry.name.clone(), guid: entry.guid.clone() });
+            }
+        }
+
+        for guid in Self::list_stored_guids().await? {
+            if !seen_guids.contains(guid.as_str()) {
+                issues.push(ConsistencyIssue::OrphanedFile { guid });
+            }
+        }
+
+        let mut checked_paths = vec![(store_path(), DIRECTORY_MODE), (Self::get_index_path().await?, FILE_MODE)];
+        for entry in entries.iter().filter(|e| e.kind != ConfigKind::Profile) {
+            checked_paths.push((Self::get_config_path(entry), FILE_MODE));
+        }
+        for (path, expected) in checked_paths {
+            if let Ok(metadata) = fs::metadata(&path).await {
+                let mode = metadata.permissions().mode() & 0o777;
+                if mode & !expected != 0 {
+                    issues.push(ConsistencyIssue::WrongPermissions {
+                        path,
+                        mode: format!("{:o}", mode),
+                        expected: format!("{:o}", expected),
+                    });
+                }
+            }
+        }
+
+        let mut unique_issues = Vec::new();
+        for issue in issues {
+            if !unique_issues.contains(&issue) {
+                unique_issues.push(issue);
+            }
+        }
+
+        Ok(unique_issues)
+    }
+
+    /// Runs [`ConfigManager::check`], then fixes every issue it found. Dead entries are dropped,
+    /// orphans are re-adopted under generated names and duplicate names get a numeric suffix.
+    pub async fn repair(&mut self) -> Result<ConsistencyReport> {
+        let issues = self.check().await?;
+        let mut repairs = Vec::new();
+
+        for issue in &issues {
+            match issue {
+                ConsistencyIssue::DuplicateGuid { guid } => {
+                    let mut first = true;
+                    self.index.entries.retain(|e| {
+                        if e.guid != *guid { return true; }
+                        let keep = first;
+                        first = false;
+                        keep
+                    });
+                    repairs.push(format!("Removed duplicate entries pointing to {}", guid));
+                }
+                ConsistencyIssue::MissingFile { name, guid } => {
+                    self.index.entries.retain(|e| e.guid != *guid);
+                    repairs.push(format!("Removed entry '{}' because its file is missing", name));
+                }
+                ConsistencyIssue::MissingTemplate { name, .. } => {
+                    self.index.entries.retain(|e| e.name != *name || e.kind != ConfigKind::Profile);
+                    repairs.push(format!("Removed profile '{}' because its template is missing", name));
+                }
+                _ => {}
+            }
+        }
+
+        for issue in &issues {
+            if let ConsistencyIssue::DuplicateName { name } = issue {
+                let duplicates = self.index.entries.iter()
+                    .enumerate()
+                    .filter(|(_, e)| e.name == *name)
+                    .map(|(i, _)| i)
+                    .skip(1)
+                    .collect::<Vec<_>>();
+                for position in duplicates {
+                    let new_name = self.generate_unique_name(name);
+                    repairs.push(format!("Renamed duplicate entry '{}' ({}) to '{}'", name, self.index.entries[position].guid, new_name));
+                    self.index.entries[position].name = new_name;
+                }
+            }
+        }
+
+        for issue in &issues {
+            if let ConsistencyIssue::OrphanedFile { guid } = issue {
+                let name = self.generate_unique_name(&format!("recovered-{}", &guid[..8]));
+                repairs.push(format!("Adopted orphaned file {}.conf as '{}'", guid, name));
+                let entry = ConfigEntry { name, guid: guid.clone(), ..Default::default() };
+                fs::set_permissions(Self::get_config_path(&entry), Permissions::from_mode(FILE_MODE)).await?;
+                self.index.entries.push(entry);
+            }
+        }
+
+        let index_path = Self::get_index_path().await?;
+        self.index.save(index_path.as_str()).await?;
+
+        for issue in &issues {
+            if let ConsistencyIssue::WrongPermissions { path, expected, .. } = issue {
+                let mode = u32::from_str_radix(expected, 8)?;
+                fs::set_permissions(path, Permissions::from_mode(mode)).await?;
+                repairs.push(format!("Changed the permissions of {} to {}", path, expected));
+            }
+        }
+
+        Ok(ConsistencyReport { issues, repairs })
+    }
+
+    fn generate_unique_name(&self, base: &str) -> String {
+        let mut candidate = base.to_string();
+        let mut counter = 1;
+        while self.index.entries.iter().any(|e| e.name == candidate) {
+            counter += 1;
+            candidate = format!("{}-{}", base, counter);
+        }
+
+        candidate
+    }
+
+    /// Returns the GUIDs of all `<guid>.conf` files found in the configs directory.
+    async fn list_stored_guids() -> Result<Vec<String>> {
+        let mut guids = Vec::new();
+        if !Path::new(&store_path()).exists() {
+            return Ok(guids);
+        }
+
+        let mut directory = fs::read_dir(store_path()).await?;
+        while let Some(file) = directory.next_entry().await? {
+            let file_name = file.file_name();
+            let Some(guid) = file_name.to_str().and_then(|n| n.strip_suffix(".conf")) else { continue };
+            if Uuid::parse_str(guid).is_ok() {
+                guids.push(guid.to_string());
+            }
+        }
+
+        Ok(guids)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::cell::RefCell;
+    use std::path::PathBuf;
+    use super::*;
+
+    thread_local! {
+        /// The config store of the test running on this thread, see [`TestDirectory::store`].
+        pub static STORE_PATH: RefCell<String> = panic!("The test did not create a config store");
+    }
+
+    /// A directory of its own for every test, removed when the test ends.
+    pub struct TestDirectory(PathBuf);
+
+    impl TestDirectory {
+        pub fn new(name: &str) -> TestDirectory {
+            let path = std::env::temp_dir().join(format!("ovpnd-{}-{}", name, Uuid::new_v4()));
+            std::fs::create_dir_all(&path).unwrap();
+            TestDirectory(path)
+        }
+
+        /// Creates an empty config store, used by the config manager of the current thread.
+        pub fn store() -> TestDirectory {
+            let directory = Self::new("store");
+            std::fs::set_permissions(&directory.0, Permissions::from_mode(DIRECTORY_MODE)).unwrap();
+            STORE_PATH.set(directory.0.to_string_lossy().to_string());
+            directory
+        }
+
+        pub fn file(&self, name: &str) -> String {
+            self.0.join(name).to_string_lossy().to_string()
+        }
+    }
+
+    impl Drop for TestDirectory {
+        fn drop(&mut self) {
+            _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    const GUID: &str = "6f1c2f1e-3b0a-4c55-9a43-0d6f2b1c9e77";
+
+    /// Writes an index as an older daemon would have, and loads it.
+    async fn load_fixture(directory: &TestDirectory, document: Value) -> Result<ConfigIndex> {
+        let index_path = directory.file("index.json");
+        std::fs::write(&index_path, document.to_string()).unwrap();
+        ConfigIndex::load(&index_path).await
+    }
+
+    #[tokio::test]
+    async fn migrates_every_old_version() {
+        for version in 0..INDEX_VERSION {
+            let directory = TestDirectory::new("migrate");
+            let mut document = serde_json::json!({ "entries": [{ "name": "work", "guid": GUID }] });
+            if version > 0 {
+                document["version"] = Value::from(version);
+            }
+
+            let index = load_fixture(&directory, document).await.unwrap();
+
+            assert_eq!(index.version, INDEX_VERSION, "migrating from version {}", version);
+            let entry = &index.entries[0];
+            assert_eq!((entry.name.as_str(), entry.guid.as_str()), ("work", GUID));
+            assert_eq!(entry.kind, ConfigKind::Config);
+            assert_eq!(entry.routes, RoutePolicy::default());
+            assert!(entry.arguments.is_empty() && entry.environment.is_empty() && entry.hooks.is_empty());
+            assert!(!entry.kill_switch && !entry.autostart && entry.last_used.is_none());
+
+            let saved: Value = serde_json::from_str(&std::fs::read_to_string(directory.file("index.json")).unwrap()).unwrap();
+            assert_eq!(saved["version"], INDEX_VERSION);
+            assert!(Path::new(&directory.file(&format!("index.json.v{}.bak", version))).exists());
+        }
+    }
+
+    #[tokio::test]
+    async fn keeps_the_fields_of_older_versions() {
+        let directory = TestDirectory::new("migrate");
+        let document = serde_json::json!({ "version": 6, "entries": [
+            { "name": "base", "guid": GUID, "kind": "Template" },
+            {
+                "name": "office",
+                "guid": "0b8e4a4e-0d55-4f0b-8b8a-52b1e5a3f0c1",
+                "kind": "Profile",
+                "template": GUID,
+                "variables": { "host": "vpn.example.com" },
+                "arguments": ["--verb 4"],
+                "environment": { "LANG": "C" },
+                "routes": { "mode": "include", "targets": ["10.0.0.0/8"] },
+                "kill_switch": true,
+                "hooks": ["/usr/local/bin/notify"]
+            }
+        ]});
+
+        let index = load_fixture(&directory, document).await.unwrap();
+
+        let profile = &index.entries[1];
+        assert_eq!(index.entries[0].kind, ConfigKind::Template);
+        assert_eq!(profile.kind, ConfigKind::Profile);
+        assert_eq!(profile.template.as_deref(), Some(GUID));
+        assert_eq!(profile.variables["host"], "vpn.example.com");
+        assert_eq!(profile.arguments, ["--verb 4"]);
+        assert_eq!(profile.environment["LANG"], "C");
+        assert_eq!(profile.routes, RoutePolicy { mode: RouteMode::Include, targets: vec!["10.0.0.0/8".to_string()] });
+        assert!(profile.kill_switch);
+        assert_eq!(profile.hooks, ["/usr/local/bin/notify"]);
+        assert!(!profile.autostart);
+    }
+
+    #[tokio::test]
+    async fn does_not_touch_a_current_index() {
+        let directory = TestDirectory::new("migrate");
+        let document = serde_json::json!({ "version": INDEX_VERSION, "entries": [{ "name": "work", "guid": GUID, "autostart": true }] });
+
+        let index = load_fixture(&directory, document).await.unwrap();
+
+        assert!(index.entries[0].autostart);
+        assert!(!Path::new(&directory.file(&format!("index.json.v{}.bak", INDEX_VERSION))).exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_a_newer_index() {
+        let directory = TestDirectory::new("migrate");
+        let document = serde_json::json!({ "version": INDEX_VERSION + 1, "entries": [] });
+
+        let error = load_fixture(&directory, document).await.unwrap_err();
+
+        assert!(error.to_string().contains("Please upgrade ovpnd"));
+        let saved: Value = serde_json::from_str(&std::fs::read_to_string(directory.file("index.json")).unwrap()).unwrap();
+        assert_eq!(saved["version"], INDEX_VERSION + 1);
+    }
+
+    const OTHER_GUID: &str = "0b8e4a4e-0d55-4f0b-8b8a-52b1e5a3f0c1";
+    const ORPHAN_GUID: &str = "c3d4e5f6-a7b8-4c9d-8e0f-112233445566";
+
+    fn entry(name: &str, guid: &str) -> ConfigEntry {
+        ConfigEntry { name: name.to_string(), guid: guid.to_string(), ..Default::default() }
+    }
+
+    /// Creates a store with the given index entries and `<guid>.conf` files.
+    async fn create_store(entries: Vec<ConfigEntry>, files: &[&str]) -> (TestDirectory, ConfigManager) {
+        let store = TestDirectory::store();
+        for guid in files {
+            let path = store.file(&format!("{}.conf", guid));
+            std::fs::write(&path, "client\n").unwrap();
+            std::fs::set_permissions(&path, Permissions::from_mode(FILE_MODE)).unwrap();
+        }
+        ConfigIndex { version: INDEX_VERSION, entries }.save(&store.file("index.json")).await.unwrap();
+
+        (store, ConfigManager::new().await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn finds_no_issues_in_a_consistent_store() {
+        let (_store, manager) = create_store(vec![entry("work", GUID), entry("home", OTHER_GUID)], &[GUID, OTHER_GUID]).await;
+
+        assert_eq!(manager.check().await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn finds_missing_and_orphaned_files() {
+        let (_store, manager) = create_store(vec![entry("work", GUID)], &[ORPHAN_GUID]).await;
+
+        assert_eq!(manager.check().await.unwrap(), [
+            ConsistencyIssue::MissingFile { name: "work".to_string(), guid: GUID.to_string() },
+            ConsistencyIssue::OrphanedFile { guid: ORPHAN_GUID.to_string() },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn finds_duplicate_names_and_guids() {
+        let entries = vec![entry("work", GUID), entry("work", OTHER_GUID), entry("copy", GUID)];
+        let (_store, manager) = create_store(entries, &[GUID, OTHER_GUID]).await;
+
+        assert_eq!(manager.check().await.unwrap(), [
+            ConsistencyIssue::DuplicateName { name: "work".to_string() },
+            ConsistencyIssue::DuplicateGuid { guid: GUID.to_string() },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn finds_profiles_without_template_and_wrong_permissions() {
+        let profile = ConfigEntry { kind: ConfigKind::Profile, template: Some(OTHER_GUID.to_string()), ..entry("office", ORPHAN_GUID) };
+        let (store, manager) = create_store(vec![entry("work", GUID), profile], &[GUID]).await;
+        std::fs::set_permissions(store.file(&format!("{}.conf", GUID)), Permissions::from_mode(0o644)).unwrap();
+
+        assert_eq!(manager.check().await.unwrap(), [
+            ConsistencyIssue::MissingTemplate { name: "office".to_string(), template: OTHER_GUID.to_string() },
+            ConsistencyIssue::WrongPermissions { path: store.file(&format!("{}.conf", GUID)), mode: "644".to_string(), expected: "600".to_string() },
+        ]);
+    }
+
+    #[tokio::test]
+    async fn check_does_not_change_the_store() {
+        let (store, manager) = create_store(vec![entry("work", GUID), entry("work", OTHER_GUID)], &[ORPHAN_GUID]).await;
+        let index = std::fs::read_to_string(store.file("index.json")).unwrap();
+
+        manager.check().await.unwrap();
+
+        assert_eq!(std::fs::read_to_string(store.file("index.json")).unwrap(), index);
+        assert_eq!(manager.get_index().get_entries().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn repairs_every_issue() {
+        let profile = ConfigEntry { kind: ConfigKind::Profile, template: Some(ORPHAN_GUID.to_string()), ..entry("office", OTHER_GUID) };
+        let entries = vec![entry("work", GUID), entry("copy", GUID), entry("missing", "d4e5f6a7-b8c9-4d0e-8f10-223344556677"), profile];
+        let (store, mut manager) = create_store(entries, &[GUID, ORPHAN_GUID]).await;
+        std::fs::set_permissions(store.file(&format!("{}.conf", GUID)), Permissions::from_mode(0o644)).unwrap();
+
+        let report = manager.repair().await.unwrap();
+
+        assert_eq!(report.issues.len(), 5);
+        assert_eq!(report.repairs.len(), 5);
+        let names = manager.get_index().get_entries().iter().map(|e| (e.name.as_str(), e.guid.as_str())).collect::<Vec<_>>();
+        assert_eq!(names, [("work", GUID), ("recovered-c3d4e5f6", ORPHAN_GUID)]);
+        let mode = std::fs::metadata(store.file(&format!("{}.conf", GUID))).unwrap().permissions().mode() & 0o777;
+        assert_eq!(mode, FILE_MODE);
+
+        let saved = ConfigIndex::load(&store.file("index.json")).await.unwrap();
+        assert_eq!(saved.entries.len(), 2);
+        assert_eq!(manager.check().await.unwrap(), []);
+    }
+
+    #[tokio::test]
+    async fn renames_duplicate_names() {
+        let entries = vec![entry("work", GUID), entry("work", OTHER_GUID), entry("work-2", ORPHAN_GUID)];
+        let (_store, mut manager) = create_store(entries, &[GUID, OTHER_GUID, ORPHAN_GUID]).await;
+
+        let report = manager.repair().await.unwrap();
+
+        assert_eq!(report.repairs, [format!("Renamed duplicate entry 'work' ({}) to 'work-3'", OTHER_GUID)]);
+        let names = manager.get_index().get_entries().iter().map(|e| e.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["work", "work-3", "work-2"]);
+    }
+
+    #[tokio::test]
+    async fn profiles_keep_earlier_variables() {
+        let _store = TestDirectory::store();
+        let mut manager = ConfigManager::new().await.unwrap();
+        manager.store(b"remote {{host}} {{port}}\n", "base".to_string()).await.unwrap();
+        manager.update("base", |entry| entry.kind = ConfigKind::Template).await.unwrap();
+        let variables = |pairs: &[(&str, &str)]| pairs.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect::<BTreeMap<_, _>>();
+
+        let missing = manager.set_profile("office".to_string(), "base".to_string(), variables(&[("host", "vpn.example.com")])).await;
+        assert_eq!(missing.unwrap_err().to_string(), "The following template variables are not set: port");
+        assert!(!manager.has_name("office"));
+
+        manager.set_profile("office".to_string(), "base".to_string(), variables(&[("host", "vpn.example.com"), ("port", "443")])).await.unwrap();
+        let profile = manager.set_profile("office".to_string(), "base".to_string(), variables(&[("port", "1194")])).await.unwrap();
+
+        assert_eq!(ConfigManager::get_config_text(&profile).await.unwrap(), "remote vpn.example.com 1194\n");
+    }
+}
@@ -0,0 +1,630 @@
+use std::fmt::{Display, Formatter};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use ini::{Ini, Properties};
+use log::LevelFilter;
+use crate::session_manager::STOP_TIMEOUT;
+
+/// openvpn directives that cannot be added as extra arguments by default, because they would
+/// replace the config, detach the process, or run arbitrary scripts with openvpn's privileges.
+const DEFAULT_DENIED_DIRECTIVES: [&str; 24] = [
+    "config", "daemon", "log", "log-append", "cd", "chroot", "script-security", "up", "down", "route-up",
+    "route-pre-down", "ipchange", "tls-verify", "auth-user-pass-verify", "client-connect", "client-disconnect",
+    "learn-address", "plugin", "iproute", "status", "writepid", "tls-export-cert", "tmp-dir", "management",
+];
+
+/// Environment variables that cannot be set for openvpn by default.
+const DEFAULT_DENIED_ENVIRONMENT: [&str; 6] = [
+    "LD_PRELOAD", "LD_LIBRARY_PATH", "LD_AUDIT", "OPENSSL_CONF", "OPENSSL_MODULES", "GCONV_PATH",
+];
+
+/// Restricts the extra arguments and environment that can be attached to a config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirectivePolicy {
+    pub denied_directives: Vec<String>,
+    pub denied_environment: Vec<String>,
+}
+
+impl Default for DirectivePolicy {
+    fn default() -> Self {
+        Self {
+            denied_directives: DEFAULT_DENIED_DIRECTIVES.iter().map(|d| d.to_string()).collect(),
+            denied_environment: DEFAULT_DENIED_ENVIRONMENT.iter().map(|e| e.to_string()).collect(),
+        }
+    }
+}
+
+impl DirectivePolicy {
+    /// Fails if any of the arguments is a denied `--directive`.
+    pub fn check_arguments(&self, arguments: &[String]) -> Result<()> {
+        for argument in arguments {
+            let Some(directive) = argument.strip_prefix("--") else { continue };
+            if self.denied_directives.iter().any(|d| d == directive) {
+                return Err(anyhow!("The directive '--{}' is not allowed by the daemon policy", directive));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn check_environment<'a, I>(&self, names: I) -> Result<()>
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        for name in names {
+            if self.denied_environment.contains(name) {
+                return Err(anyhow!("The environment variable '{}' is not allowed by the daemon policy", name));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Where the daemon and openvpn output is logged.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum LogBackendKind {
+    /// Plain lines on stderr, which systemd also forwards to the journal, without fields.
+    #[default]
+    Stderr,
+    /// Native journald entries with structured fields, falling back to stderr if the journal
+    /// cannot be reached.
+    Journald,
+}
+
+impl FromStr for LogBackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "stderr" => Ok(LogBackendKind::Stderr),
+            "journald" => Ok(LogBackendKind::Journald),
+            other => Err(anyhow!("Unknown log backend '{}', expected stderr or journald", other)),
+        }
+    }
+}
+
+/// How pushed DNS servers and search domains are applied to the tunnel link.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum DnsBackendKind {
+    /// Pushed DNS settings are ignored, e.g. because up/down scripts take care of them.
+    #[default]
+    None,
+    /// systemd-resolved, configured through `resolvectl`.
+    Resolved,
+    /// `resolvconf` (openresolv or Debian's resolvconf).
+    Resolvconf,
+}
+
+impl FromStr for DnsBackendKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "none" => Ok(DnsBackendKind::None),
+            "resolved" => Ok(DnsBackendKind::Resolved),
+            "resolvconf" => Ok(DnsBackendKind::Resolvconf),
+            other => Err(anyhow!("Unknown DNS backend '{}', expected none, resolved or resolvconf", other)),
+        }
+    }
+}
+
+/// Controls the lifecycle hooks run by the daemon.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HookSettings {
+    pub timeout: Duration,
+
+    /// Whether configs may name their own hooks. Anyone with access to the socket can change
+    /// configs, and hooks run as root, so this is off unless the administrator enables it.
+    pub allow_config_hooks: bool,
+}
+
+impl Default for HookSettings {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+            allow_config_hooks: false,
+        }
+    }
+}
+
+/// Which session the daemon starts by itself when it starts.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum StartupPolicy {
+    #[default]
+    None,
+    /// The config that was used most recently.
+    LastUsed,
+    /// The configs listed in the startup settings, then the ones marked with `--autostart`.
+    Autostart,
+}
+
+impl FromStr for StartupPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "none" => Ok(StartupPolicy::None),
+            "last-used" => Ok(StartupPolicy::LastUsed),
+            "autostart" => Ok(StartupPolicy::Autostart),
+            other => Err(anyhow!("Unknown startup policy '{}', expected none, last-used or autostart", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct StartupSettings {
+    pub policy: StartupPolicy,
+
+    /// Names of the configs to try first with the `autostart` policy, in order.
+    pub configs: Vec<String>,
+}
+
+/// What happens to the active session when the daemon shuts down.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum ShutdownPolicy {
+    /// The session is stopped like with `ovpn-cli session stop`.
+    #[default]
+    Stop,
+    /// openvpn keeps running, and the daemon reattaches to it when it starts again.
+    Keep,
+}
+
+impl FromStr for ShutdownPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "stop" => Ok(ShutdownPolicy::Stop),
+            "keep" => Ok(ShutdownPolicy::Keep),
+            other => Err(anyhow!("Unknown shutdown policy '{}', expected stop or keep", other)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShutdownSettings {
+    pub sessions: ShutdownPolicy,
+
+    /// How long stopping the sessions may take before openvpn is killed.
+    pub timeout: Duration,
+}
+
+impl Default for ShutdownSettings {
+    fn default() -> Self {
+        Self {
+            sessions: ShutdownPolicy::Stop,
+            timeout: Self::default_timeout(HookSettings::default().timeout),
+        }
+    }
+}
+
+impl ShutdownSettings {
+    /// Time for openvpn to exit and for the hooks of the stopped session to run, with a margin
+    /// for reverting the DNS settings and removing the kill switch.
+    pub fn default_timeout(hook_timeout: Duration) -> Duration {
+        STOP_TIMEOUT + hook_timeout + Duration::from_secs(5)
+    }
+}
+
+/// Which past sessions are kept in the history.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistorySettings {
+    pub enabled: bool,
+
+    /// Records of sessions that started longer ago are removed.
+    pub max_age: Duration,
+
+    /// Only the most recent records are kept.
+    pub max_records: usize,
+}
+
+impl Default for HistorySettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_age: Duration::from_secs(90 * 24 * 60 * 60),
+            max_records: 1000,
+        }
+    }
+}
+
+/// Where an HTTP endpoint of the daemon listens.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddress {
+    Tcp(SocketAddr),
+    Unix(String),
+}
+
+impl FromStr for ListenAddress {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        if let Some(path) = value.strip_prefix("unix:") {
+            return Ok(ListenAddress::Unix(path.to_string()));
+        }
+
+        value.parse()
+            .map(ListenAddress::Tcp)
+            .with_context(|| format!("Invalid listen address '{}', expected an address such as 127.0.0.1:9477 or unix:/path", value))
+    }
+}
+
+impl Display for ListenAddress {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ListenAddress::Tcp(address) => write!(f, "{}", address),
+            ListenAddress::Unix(path) => write!(f, "unix:{}", path),
+        }
+    }
+}
+
+/// The OpenMetrics endpoint, which is disabled unless an address is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricsSettings {
+    pub listen: Option<ListenAddress>,
+
+    /// Permissions of the unix socket, if the endpoint listens on one.
+    pub mode: u32,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            mode: 0o660,
+        }
+    }
+}
+
+/// The HTTP API, which is disabled unless an address is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiSettings {
+    pub listen: Option<ListenAddress>,
+
+    /// Bearer token clients must send, required when listening on a TCP port.
+    pub token: Option<String>,
+
+    /// Permissions of the unix socket, if the API listens on one.
+    pub mode: u32,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            token: None,
+            mode: 0o660,
+        }
+    }
+}
+
+/// The WebSocket bridge for status bar widgets, which is disabled unless an address is set.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WebSocketSettings {
+    pub listen: Option<ListenAddress>,
+
+    /// Token clients must pass as the `token` query parameter, if set.
+    pub token: Option<String>,
+
+    /// Origins of the web pages that may connect, `*` allows any. Browsers send an `Origin`
+    /// header, so without this any page the user opens could control the daemon. Clients that
+    /// send no origin, such as desktop widgets, are always accepted.
+    pub allowed_origins: Vec<String>,
+
+    /// Permissions of the unix socket, if the bridge listens on one.
+    pub mode: u32,
+}
+
+impl Default for WebSocketSettings {
+    fn default() -> Self {
+        Self {
+            listen: None,
+            token: None,
+            allowed_origins: Vec::new(),
+            mode: 0o660,
+        }
+    }
+}
+
+/// The message bus the D-Bus interface is exported on.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum DbusBus {
+    #[default]
+    System,
+    /// The bus of the user session, e.g. a private `dbus-daemon` for testing.
+    Session,
+    /// A bus address such as `unix:path=/tmp/bus`.
+    Address(String),
+}
+
+impl FromStr for DbusBus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "system" => Ok(DbusBus::System),
+            "session" => Ok(DbusBus::Session),
+            address if address.contains(':') => Ok(DbusBus::Address(address.to_string())),
+            _ => Err(anyhow!("Invalid D-Bus bus '{}', expected system, session or a bus address", value)),
+        }
+    }
+}
+
+impl Display for DbusBus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DbusBus::System => write!(f, "system"),
+            DbusBus::Session => write!(f, "session"),
+            DbusBus::Address(address) => write!(f, "{}", address),
+        }
+    }
+}
+
+/// The `org.ovpn.Daemon1` D-Bus interface, which is disabled by default.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DbusSettings {
+    pub enabled: bool,
+    pub bus: DbusBus,
+}
+
+/// How openvpn, and the daemon itself, get by with fewer privileges.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrivilegeSettings {
+    /// User and group openvpn switches to once the tunnel is up (`--user`/`--group`).
+    pub openvpn_user: Option<String>,
+    pub openvpn_group: Option<String>,
+
+    /// Adds `--persist-tun` and `--persist-key` together with the user or group, since openvpn
+    /// cannot reopen them without its privileges when it reconnects.
+    pub persist: bool,
+
+    /// Persistent tun device openvpn uses instead of creating one, e.g. when the daemon runs
+    /// unprivileged and the device is created for it at service start.
+    pub tun_device: Option<String>,
+}
+
+impl Default for PrivilegeSettings {
+    fn default() -> Self {
+        Self {
+            openvpn_user: None,
+            openvpn_group: None,
+            persist: true,
+            tun_device: None,
+        }
+    }
+}
+
+/// Settings of the daemon itself, read from an INI file. Every setting is optional.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DaemonConfig {
+    pub log_level: LevelFilter,
+    pub log_backend: LogBackendKind,
+
+    /// Permissions of the socket file, all users can connect by default.
+    pub socket_mode: u32,
+
+    pub policy: DirectivePolicy,
+    pub dns_backend: DnsBackendKind,
+    pub hooks: HookSettings,
+    pub startup: StartupSettings,
+    pub shutdown: ShutdownSettings,
+    pub privileges: PrivilegeSettings,
+    pub history: HistorySettings,
+    pub metrics: MetricsSettings,
+    pub api: ApiSettings,
+    pub dbus: DbusSettings,
+    pub websocket: WebSocketSettings,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            log_level: LevelFilter::Info,
+            log_backend: LogBackendKind::default(),
+            socket_mode: 0o777,
+            policy: DirectivePolicy::default(),
+            dns_backend: DnsBackendKind::default(),
+            hooks: HookSettings::default(),
+            startup: StartupSettings::default(),
+            shutdown: ShutdownSettings::default(),
+            privileges: PrivilegeSettings::default(),
+            history: HistorySettings::default(),
+            metrics: MetricsSettings::default(),
+            api: ApiSettings::default(),
+            dbus: DbusSettings::default(),
+            websocket: WebSocketSettings::default(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    pub fn load(path: &str) -> Result<DaemonConfig> {
+        if !Path::new(path).exists() {
+            return Ok(DaemonConfig::default());
+        }
+
+        let ini = Ini::load_from_file(path)
+            .with_context(|| format!("Failed to read the daemon configuration file '{}'", path))?;
+        let mut config = DaemonConfig::default();
+
+        if let Some(section) = ini.section(Some("log")) {
+            if let Some(level) = section.get("level") {
+                config.log_level = level.parse().with_context(|| format!("Invalid log level '{}'", level))?;
+            }
+            if let Some(backend) = section.get("backend") {
+                config.log_backend = backend.parse()?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("socket")) {
+            if let Some(mode) = section.get("mode") {
+                config.socket_mode = Self::parse_mode(mode)?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("policy")) {
+            if let Some(directives) = Self::get_list(section, "denied_directives") {
+                config.policy.denied_directives = directives;
+            }
+            if let Some(environment) = Self::get_list(section, "denied_environment") {
+                config.policy.denied_environment = environment;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("dns")) {
+            if let Some(backend) = section.get("backend") {
+                config.dns_backend = backend.parse()?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("hooks")) {
+            if let Some(timeout) = section.get("timeout") {
+                let seconds = timeout.parse().with_context(|| format!("Invalid hook timeout '{}'", timeout))?;
+                config.hooks.timeout = Duration::from_secs(seconds);
+            }
+            if let Some(allow) = section.get("allow_config_hooks") {
+                config.hooks.allow_config_hooks = allow.parse().with_context(|| format!("Invalid allow_config_hooks value '{}'", allow))?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("startup")) {
+            if let Some(policy) = section.get("policy") {
+                config.startup.policy = policy.parse()?;
+            }
+            if let Some(configs) = Self::get_list(section, "configs") {
+                config.startup.configs = configs;
+            }
+        }
+
+        config.shutdown.timeout = ShutdownSettings::default_timeout(config.hooks.timeout);
+        if let Some(section) = ini.section(Some("shutdown")) {
+            if let Some(sessions) = section.get("sessions") {
+                config.shutdown.sessions = sessions.parse()?;
+            }
+            if let Some(timeout) = section.get("timeout") {
+                let seconds = timeout.parse().with_context(|| format!("Invalid shutdown timeout '{}'", timeout))?;
+                config.shutdown.timeout = Duration::from_secs(seconds);
+            }
+        }
+
+        if let Some(section) = ini.section(Some("privileges")) {
+            config.privileges.openvpn_user = section.get("openvpn_user").map(|user| user.to_string());
+            config.privileges.openvpn_group = section.get("openvpn_group").map(|group| group.to_string());
+            config.privileges.tun_device = section.get("tun_device").map(|device| device.to_string());
+            if let Some(persist) = section.get("persist") {
+                config.privileges.persist = persist.parse().with_context(|| format!("Invalid persist value '{}'", persist))?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("history")) {
+            if let Some(enabled) = section.get("enabled") {
+                config.history.enabled = enabled.parse().with_context(|| format!("Invalid enabled value '{}'", enabled))?;
+            }
+            if let Some(days) = section.get("max_age_days") {
+                let days: u64 = days.parse().with_context(|| format!("Invalid history max_age_days '{}'", days))?;
+                config.history.max_age = Duration::from_secs(days * 24 * 60 * 60);
+            }
+            if let Some(records) = section.get("max_records") {
+                config.history.max_records = records.parse().with_context(|| format!("Invalid history max_records '{}'", records))?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("metrics")) {
+            config.metrics.listen = section.get("listen").map(|listen| listen.parse()).transpose()?;
+            if let Some(mode) = section.get("mode") {
+                config.metrics.mode = Self::parse_mode(mode)?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("api")) {
+            config.api.listen = section.get("listen").map(|listen| listen.parse()).transpose()?;
+            config.api.token = section.get("token").map(|token| token.to_string());
+            if let Some(path) = section.get("token_file") {
+                let token = std::fs::read_to_string(path).with_context(|| format!("Failed to read the API token file '{}'", path))?;
+                config.api.token = Some(token.trim().to_string());
+            }
+            if let Some(mode) = section.get("mode") {
+                config.api.mode = Self::parse_mode(mode)?;
+            }
+
+            // Anyone who can reach the port controls the daemon, so it is limited to the local
+            // machine and to clients that know the token
+            if let Some(ListenAddress::Tcp(address)) = &config.api.listen {
+                if !address.ip().is_loopback() {
+                    return Err(anyhow!("The API can only listen on a loopback address, not {}", address));
+                }
+                if config.api.token.as_ref().is_none_or(|token| token.is_empty()) {
+                    return Err(anyhow!("The API needs a token when it listens on a TCP port"));
+                }
+            }
+        }
+
+        if let Some(section) = ini.section(Some("dbus")) {
+            if let Some(enabled) = section.get("enabled") {
+                config.dbus.enabled = enabled.parse().with_context(|| format!("Invalid enabled value '{}'", enabled))?;
+            }
+            if let Some(bus) = section.get("bus") {
+                config.dbus.bus = bus.parse()?;
+            }
+        }
+
+        if let Some(section) = ini.section(Some("websocket")) {
+            config.websocket.listen = section.get("listen").map(|listen| listen.parse()).transpose()?;
+            config.websocket.token = section.get("token").map(|token| token.to_string());
+            if let Some(origins) = Self::get_list(section, "allowed_origins") {
+                config.websocket.allowed_origins = origins;
+            }
+            if let Some(mode) = section.get("mode") {
+                config.websocket.mode = Self::parse_mode(mode)?;
+            }
+
+            if let Some(ListenAddress::Tcp(address)) = &config.websocket.listen {
+                if !address.ip().is_loopback() {
+                    return Err(anyhow!("The WebSocket bridge can only listen on a loopback address, not {}", address));
+                }
+            }
+        }
+
+        Ok(config)
+    }
+
+    fn parse_mode(mode: &str) -> Result<u32> {
+        u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|mode| *mode <= 0o777)
+            .with_context(|| format!("Invalid socket mode '{}', expected an octal mode such as 0660", mode))
+    }
+
+    /// Names the sections whose settings differ from `other`.
+    pub fn changed_sections(&self, other: &DaemonConfig) -> Vec<&'static str> {
+        let sections = [
+            ("log", self.log_level != other.log_level || self.log_backend != other.log_backend),
+            ("socket", self.socket_mode != other.socket_mode),
+            ("policy", self.policy != other.policy),
+            ("dns", self.dns_backend != other.dns_backend),
+            ("hooks", self.hooks != other.hooks),
+            ("startup", self.startup != other.startup),
+            ("shutdown", self.shutdown != other.shutdown),
+            ("privileges", self.privileges != other.privileges),
+            ("history", self.history != other.history),
+            ("metrics", self.metrics != other.metrics),
+            ("api", self.api != other.api),
+            ("dbus", self.dbus != other.dbus),
+            ("websocket", self.websocket != other.websocket),
+        ];
+
+        sections.into_iter().filter(|(_, changed)| *changed).map(|(name, _)| name).collect()
+    }
+
+    /// Reads a comma-separated list value.
+    fn get_list(section: &Properties, key: &str) -> Option<Vec<String>> {
+        section.get(key).map(|value| value.split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect())
+    }
+}
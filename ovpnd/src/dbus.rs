@@ -0,0 +1,209 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use log::{error, info};
+use tokio::sync::broadcast::error::RecvError;
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Value};
+use zbus::{connection, fdo, interface, Connection};
+use common::command::{Cli, Commands, ConfigCommand, ConflictStrategy, ExportFormat, SessionCommand};
+use crate::command_handler::CommandHandler;
+use crate::daemon_config::{DbusBus, DbusSettings};
+use crate::events::Event;
+use crate::response::Response;
+use crate::session::SessionStatus;
+use crate::state::AppState;
+
+const SERVICE_NAME: &str = "org.ovpn.Daemon1";
+const OBJECT_PATH: &str = "/org/ovpn/Daemon1";
+
+/// Polkit actions, see `org.ovpn.daemon.policy`.
+const MANAGE_CONFIGS_ACTION: &str = "org.ovpn.daemon.manage-configs";
+const CONTROL_SESSIONS_ACTION: &str = "org.ovpn.daemon.control-sessions";
+
+const POLKIT_SERVICE: &str = "org.freedesktop.PolicyKit1";
+const POLKIT_PATH: &str = "/org/freedesktop/PolicyKit1/Authority";
+const POLKIT_INTERFACE: &str = "org.freedesktop.PolicyKit1.Authority";
+
+/// Lets polkit ask the user for a password if the action requires it.
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// The `org.ovpn.Daemon1` interface, which runs the same commands as the socket. On the system
+/// bus the callers of the methods that change anything are authorized through polkit.
+pub struct Daemon {
+    app_state: Arc<AppState>,
+    polkit: bool,
+}
+
+impl Daemon {
+    /// Exports the interface on the configured bus, and forwards the session events as signals
+    /// until the daemon exits.
+    pub async fn serve(settings: DbusSettings, app_state: Arc<AppState>) {
+        if !settings.enabled {
+            return;
+        }
+
+        let mut events = app_state.socket_manager.lock().await.subscribe();
+        let connection = match Self::connect(&settings.bus, Arc::clone(&app_state)).await {
+            Ok(connection) => connection,
+            Err(e) => return error!("Failed to export the D-Bus interface: {}", e),
+        };
+        info!("D-Bus interface exported as {} on bus {}", SERVICE_NAME, settings.bus);
+
+        loop {
+            match events.recv().await {
+                Ok(Event::Status(session)) => {
+                    let status = format!("{:?}", session.status);
+                    if let Err(e) = Self::emit_status_changed(&connection, &session.config.guid, &session.config.name, &status).await {
+                        error!("Failed to emit the D-Bus status signal: {}", e);
+                    }
+                }
+                Ok(_) | Err(RecvError::Lagged(_)) => {}
+                Err(RecvError::Closed) => return,
+            }
+        }
+    }
+
+    async fn connect(bus: &DbusBus, app_state: Arc<AppState>) -> Result<Connection> {
+        let builder = match bus {
+            DbusBus::System => connection::Builder::system()?,
+            DbusBus::Session => connection::Builder::session()?,
+            DbusBus::Address(address) => connection::Builder::address(address.as_str())?,
+        };
+
+        // Only the system bus is shared between users, a session bus belongs to the caller
+        let daemon = Daemon { app_state, polkit: *bus == DbusBus::System };
+        Ok(builder.name(SERVICE_NAME)?.serve_at(OBJECT_PATH, daemon)?.build().await?)
+    }
+
+    async fn emit_status_changed(connection: &Connection, guid: &str, name: &str, status: &str) -> Result<()> {
+        let interface = connection.object_server().interface::<_, Daemon>(OBJECT_PATH).await?;
+        Daemon::session_status_changed(interface.signal_emitter(), guid, name, status).await?;
+        interface.get().await.active_sessions_changed(interface.signal_emitter()).await?;
+        Ok(())
+    }
+
+    /// Fails unless polkit allows the caller to perform the action.
+    async fn authorize(&self, connection: &Connection, header: &Header<'_>, action: &str) -> fdo::Result<()> {
+        if !self.polkit {
+            return Ok(());
+        }
+
+        let sender = header.sender().ok_or_else(|| fdo::Error::AccessDenied("The caller is unknown".to_string()))?;
+        let subject = ("system-bus-name", HashMap::from([("name", Value::from(sender.as_str()))]));
+        let details: HashMap<&str, &str> = HashMap::new();
+        let reply = connection.call_method(Some(POLKIT_SERVICE), POLKIT_PATH, Some(POLKIT_INTERFACE), "CheckAuthorization",
+            &(subject, action, details, ALLOW_USER_INTERACTION, "")).await
+            .map_err(|e| fdo::Error::AccessDenied(format!("Failed to check the authorization: {}", e)))?;
+        let (authorized, _, _): (bool, bool, HashMap<String, String>) = reply.body().deserialize()?;
+
+        match authorized {
+            true => Ok(()),
+            false => Err(fdo::Error::AccessDenied(format!("Not authorized to perform {}", action))),
+        }
+    }
+
+    async fn dispatch(&self, commands: Commands) -> fdo::Result<String> {
+        let response = CommandHandler::handle_command(Cli { commands }, Arc::clone(&self.app_state)).await;
+        Self::into_reply(response)
+    }
+
+    fn into_reply(response: Result<Option<Response>>) -> fdo::Result<String> {
+        let response = response
+            .and_then(|response| response.ok_or_else(|| anyhow!("The command did not return a response")))
+            .map_err(|e| fdo::Error::Failed(e.to_string()))?;
+
+        match response.is_success() {
+            true => Ok(response.message().to_string()),
+            false => Err(fdo::Error::Failed(response.message().to_string())),
+        }
+    }
+}
+
+#[interface(name = "org.ovpn.Daemon1")]
+impl Daemon {
+    /// The config index as JSON, like `config list`.
+    async fn list_configs(&self) -> fdo::Result<String> {
+        self.dispatch(Commands::Config { commands: ConfigCommand::List }).await
+    }
+
+    /// Imports a config (format `ovpn`) or an archive of configs (`zip` or `tar.gz`). The options
+    /// are `name` and `conflict` (skip, rename or overwrite) strings, and `template` and
+    /// `dry_run` booleans.
+    async fn import_config(
+        &self,
+        data: Vec<u8>,
+        format: &str,
+        options: HashMap<String, OwnedValue>,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<String> {
+        self.authorize(connection, &header, MANAGE_CONFIGS_ACTION).await?;
+
+        let extension = match format {
+            "ovpn" | "zip" | "tar.gz" => format,
+            _ => return Err(fdo::Error::InvalidArgs(format!("Cannot import '{}', expected ovpn, zip or tar.gz", format))),
+        };
+        let string = |key: &str| options.get(key).and_then(|value| String::try_from(value.clone()).ok());
+        let flag = |key: &str| options.get(key).and_then(|value| bool::try_from(value).ok()).unwrap_or(false);
+
+        let name = string("name");
+        if extension == "ovpn" && name.is_none() {
+            return Err(fdo::Error::InvalidArgs("The name of the configuration is required".to_string()));
+        }
+        let conflict = string("conflict").map(|value| ConflictStrategy::from_str(&value, true)).transpose()
+            .map_err(|_| fdo::Error::InvalidArgs("The conflict strategy must be one of skip, rename or overwrite".to_string()))?;
+
+        let response = CommandHandler::import_data(&data, extension, name, conflict, flag("dry_run"), flag("template"), Arc::clone(&self.app_state)).await;
+        Self::into_reply(response)
+    }
+
+    /// The config text, or a base64 encoded bundle with format `bundle`, like `config export`.
+    async fn export_config(
+        &self,
+        name: String,
+        format: &str,
+        include_credentials: bool,
+        #[zbus(connection)] connection: &Connection,
+        #[zbus(header)] header: Header<'_>,
+    ) -> fdo::Result<String> {
+        self.authorize(connection, &header, MANAGE_CONFIGS_ACTION).await?;
+
+        let format = ExportFormat::from_str(format, true)
+            .map_err(|_| fdo::Error::InvalidArgs(format!("Cannot export as '{}', expected config or bundle", format)))?;
+        self.dispatch(Commands::Config { commands: ConfigCommand::Export { name, format, include_credentials, output: None } }).await
+    }
+
+    async fn delete_config(&self, name: String, #[zbus(connection)] connection: &Connection, #[zbus(header)] header: Header<'_>) -> fdo::Result<String> {
+        self.authorize(connection, &header, MANAGE_CONFIGS_ACTION).await?;
+        self.dispatch(Commands::Config { commands: ConfigCommand::Delete { name } }).await
+    }
+
+    async fn start_session(&self, name: String, #[zbus(connection)] connection: &Connection, #[zbus(header)] header: Header<'_>) -> fdo::Result<String> {
+        self.authorize(connection, &header, CONTROL_SESSIONS_ACTION).await?;
+        self.dispatch(Commands::Session { commands: SessionCommand::Start { name } }).await
+    }
+
+    async fn stop_session(&self, #[zbus(connection)] connection: &Connection, #[zbus(header)] header: Header<'_>) -> fdo::Result<String> {
+        self.authorize(connection, &header, CONTROL_SESSIONS_ACTION).await?;
+        self.dispatch(Commands::Session { commands: SessionCommand::Stop }).await
+    }
+
+    /// The guid, config name and status of the sessions that have not ended.
+    #[zbus(property)]
+    async fn active_sessions(&self) -> Vec<(String, String, String)> {
+        let active_session = self.app_state.active_session.read().await;
+        let Some(session) = active_session.as_ref() else { return Vec::new() };
+
+        let session = session.to_serializable().await;
+        if matches!(session.status, SessionStatus::Stopped | SessionStatus::Failed) {
+            return Vec::new();
+        }
+        vec![(session.config.guid, session.config.name, format!("{:?}", session.status))]
+    }
+
+    #[zbus(signal)]
+    async fn session_status_changed(emitter: &SignalEmitter<'_>, guid: &str, name: &str, status: &str) -> zbus::Result<()>;
+}
@@ -0,0 +1,198 @@
+use std::sync::Arc;
+use anyhow::Result;
+use log::info;
+use tokio::sync::Mutex;
+use crate::daemon_config::DnsBackendKind;
+use crate::system::run;
+
+/// Applies DNS settings to a network link and reverts them again.
+pub trait DnsBackend: Send + Sync {
+    fn apply(&self, link: &str, servers: &[String], domains: &[String]) -> Result<()>;
+    fn revert(&self, link: &str) -> Result<()>;
+}
+
+/// Uses `resolvectl` to configure the link in systemd-resolved.
+pub struct ResolvedBackend {}
+
+impl DnsBackend for ResolvedBackend {
+    fn apply(&self, link: &str, servers: &[String], domains: &[String]) -> Result<()> {
+        run("resolvectl", &[&["dns", link], to_str(servers).as_slice()].concat(), None)?;
+        if !domains.is_empty() {
+            run("resolvectl", &[&["domain", link], to_str(domains).as_slice()].concat(), None)?;
+        }
+        // Without a default route, only the search domains would be resolved through the tunnel
+        run("resolvectl", &["default-route", link, "yes"], None)?;
+        Ok(())
+    }
+
+    fn revert(&self, link: &str) -> Result<()> {
+        run("resolvectl", &["revert", link], None)?;
+        Ok(())
+    }
+}
+
+/// Uses `resolvconf` to add and remove an interface record.
+pub struct ResolvconfBackend {}
+
+impl ResolvconfBackend {
+    fn record(link: &str) -> String {
+        format!("{}.ovpnd", link)
+    }
+}
+
+impl DnsBackend for ResolvconfBackend {
+    fn apply(&self, link: &str, servers: &[String], domains: &[String]) -> Result<()> {
+        let mut contents = String::new();
+        for server in servers {
+            contents.push_str(&format!("nameserver {}\n", server));
+        }
+        if !domains.is_empty() {
+            contents.push_str(&format!("search {}\n", domains.join(" ")));
+        }
+
+        run("resolvconf", &["-a", &Self::record(link), "-m", "0", "-x"], Some(&contents))?;
+        Ok(())
+    }
+
+    fn revert(&self, link: &str) -> Result<()> {
+        run("resolvconf", &["-d", &Self::record(link), "-f"], None)?;
+        Ok(())
+    }
+}
+
+fn to_str(values: &[String]) -> Vec<&str> {
+    values.iter().map(|v| v.as_str()).collect()
+}
+
+/// Tracks which link has DNS settings applied, so they can be reverted exactly once.
+pub struct DnsManager {
+    backend: Option<Arc<dyn DnsBackend>>,
+    applied_link: Mutex<Option<String>>,
+}
+
+impl DnsManager {
+    pub fn new(kind: DnsBackendKind) -> DnsManager {
+        let backend: Option<Arc<dyn DnsBackend>> = match kind {
+            DnsBackendKind::None => None,
+            DnsBackendKind::Resolved => Some(Arc::new(ResolvedBackend {})),
+            DnsBackendKind::Resolvconf => Some(Arc::new(ResolvconfBackend {})),
+        };
+
+        Self::with_backend(backend)
+    }
+
+    pub fn with_backend(backend: Option<Arc<dyn DnsBackend>>) -> DnsManager {
+        Self {
+            backend,
+            applied_link: Mutex::new(None),
+        }
+    }
+
+    pub async fn apply(&self, link: &str, servers: &[String], domains: &[String]) -> Result<()> {
+        let Some(backend) = self.backend.clone() else { return Ok(()) };
+        if servers.is_empty() && domains.is_empty() {
+            return Ok(());
+        }
+
+        let mut applied_link = self.applied_link.lock().await;
+        let (link, servers, domains) = (link.to_string(), servers.to_vec(), domains.to_vec());
+        let link_clone = link.clone();
+        tokio::task::spawn_blocking(move || backend.apply(&link_clone, &servers, &domains)).await??;
+
+        info!("Applied pushed DNS settings to {}", link);
+        *applied_link = Some(link);
+        Ok(())
+    }
+
+    /// Records settings applied by an earlier run of the daemon, so they are reverted as well.
+    pub async fn assume_applied(&self, link: &str) {
+        if self.backend.is_some() {
+            *self.applied_link.lock().await = Some(link.to_string());
+        }
+    }
+
+    /// Reverts the settings applied by [`DnsManager::apply`], if any.
+    pub async fn revert(&self) -> Result<()> {
+        let Some(backend) = self.backend.clone() else { return Ok(()) };
+        let mut applied_link = self.applied_link.lock().await;
+        let Some(link) = applied_link.take() else { return Ok(()) };
+
+        let link_clone = link.clone();
+        tokio::task::spawn_blocking(move || backend.revert(&link_clone)).await??;
+
+        info!("Reverted DNS settings of {}", link);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    /// Records the calls instead of changing the system configuration.
+    #[derive(Default)]
+    struct RecordingBackend {
+        calls: Mutex<Vec<String>>,
+    }
+
+    impl DnsBackend for RecordingBackend {
+        fn apply(&self, link: &str, servers: &[String], domains: &[String]) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("apply {} {} {}", link, servers.join(","), domains.join(",")));
+            Ok(())
+        }
+
+        fn revert(&self, link: &str) -> Result<()> {
+            self.calls.lock().unwrap().push(format!("revert {}", link));
+            Ok(())
+        }
+    }
+
+    fn manager() -> (Arc<RecordingBackend>, DnsManager) {
+        let backend = Arc::new(RecordingBackend::default());
+        let manager = DnsManager::with_backend(Some(backend.clone()));
+        (backend, manager)
+    }
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|v| v.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn applies_and_reverts_once() {
+        let (backend, manager) = manager();
+        manager.apply("tun0", &strings(&["10.8.0.1", "10.8.0.2"]), &strings(&["corp.example"])).await.unwrap();
+        manager.revert().await.unwrap();
+        manager.revert().await.unwrap();
+
+        assert_eq!(*backend.calls.lock().unwrap(), ["apply tun0 10.8.0.1,10.8.0.2 corp.example", "revert tun0"]);
+    }
+
+    #[tokio::test]
+    async fn skips_empty_settings() {
+        let (backend, manager) = manager();
+        manager.apply("tun0", &[], &[]).await.unwrap();
+        manager.revert().await.unwrap();
+
+        assert!(backend.calls.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn reverts_assumed_link() {
+        let (backend, manager) = manager();
+        manager.assume_applied("tun1").await;
+        manager.revert().await.unwrap();
+
+        assert_eq!(*backend.calls.lock().unwrap(), ["revert tun1"]);
+    }
+
+    #[tokio::test]
+    async fn does_nothing_without_backend() {
+        let manager = DnsManager::with_backend(None);
+        manager.assume_applied("tun0").await;
+        manager.apply("tun0", &strings(&["10.8.0.1"]), &[]).await.unwrap();
+        manager.revert().await.unwrap();
+
+        assert!(manager.applied_link.lock().await.is_none());
+    }
+}
@@ -0,0 +1,45 @@
+use serde::Serialize;
+use crate::session::SerializableSession;
+
+/// Something the clients of the daemon are notified about. Socket clients receive these in the
+/// `!` line format, the other interfaces subscribe through `SocketManager::subscribe`.
+#[derive(Clone, Debug)]
+pub enum Event {
+    Status(Box<SerializableSession>),
+    Daemon(DaemonEvent),
+    Traffic(TrafficEvent),
+}
+
+/// An event that is not about a session, such as `autostart` or `stopping`.
+#[derive(Serialize, Clone, Debug)]
+pub struct DaemonEvent {
+    pub kind: String,
+    pub message: String,
+}
+
+/// The traffic counters of the active session, sampled every few seconds.
+#[derive(Serialize, Clone, Debug)]
+pub struct TrafficEvent {
+    pub guid: String,
+    pub name: String,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Event::Status(_) => "status",
+            Event::Daemon(_) => "daemon",
+            Event::Traffic(_) => "traffic",
+        }
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        match self {
+            Event::Status(session) => serde_json::to_string(session),
+            Event::Daemon(event) => serde_json::to_string(event),
+            Event::Traffic(event) => serde_json::to_string(event),
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use common::paths::{HISTORY_PATH, STATE_PATH};
+use crate::daemon_config::HistorySettings;
+use crate::session::{Session, SessionStatus};
+
+const FILE_MODE: u32 = 0o600;
+
+/// A finished session.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HistoryRecord {
+    pub guid: String,
+
+    /// Name of the config when the session ended, it may have been renamed since.
+    pub name: String,
+
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    pub duration_seconds: i64,
+
+    /// `Stopped` or `Failed`.
+    pub final_state: SessionStatus,
+    pub exit_reason: String,
+
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+/// Past sessions, stored as one JSON record per line.
+pub struct History {}
+
+impl History {
+    /// Describes a session that just ended.
+    pub async fn describe(session: &Session, final_state: SessionStatus, exit_reason: &str) -> HistoryRecord {
+        let ended = Utc::now();
+        let info = session.info.read().await;
+
+        HistoryRecord {
+            guid: session.config.guid.clone(),
+            name: session.config.name.clone(),
+            started: session.started,
+            ended,
+            duration_seconds: (ended - session.started).num_seconds(),
+            final_state,
+            exit_reason: exit_reason.to_string(),
+            bytes_received: info.bytes_received,
+            bytes_sent: info.bytes_sent,
+        }
+    }
+
+    /// Appends a record, and removes the ones that fall outside the retention settings.
+    pub async fn record(settings: &HistorySettings, record: HistoryRecord) -> Result<()> {
+        if !settings.enabled {
+            return Ok(());
+        }
+
+        let mut records = Self::load().await?;
+        records.push(record);
+
+        let oldest = Utc::now() - TimeDelta::from_std(settings.max_age)?;
+        records.retain(|record| record.started >= oldest);
+        let excess = records.len().saturating_sub(settings.max_records);
+        records.drain(..excess);
+
+        let mut data = String::new();
+        for record in &records {
+            data.push_str(&serde_json::to_string(record)?);
+            data.push('\n');
+        }
+
+        // Written next to the store and renamed, so a crash cannot truncate the history
+        let temporary_path = format!("{}.tmp", HISTORY_PATH);
+        fs::create_dir_all(STATE_PATH).await?;
+        fs::write(&temporary_path, data).await?;
+        fs::set_permissions(&temporary_path, Permissions::from_mode(FILE_MODE)).await?;
+        fs::rename(&temporary_path, HISTORY_PATH).await
+            .context("Failed to update the session history")?;
+        Ok(())
+    }
+
+    /// Returns the records in the order they were added, skipping lines that cannot be parsed.
+    pub async fn load() -> Result<Vec<HistoryRecord>> {
+        let data = match fs::read_to_string(HISTORY_PATH).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).context("Failed to read the session history"),
+        };
+
+        let mut records = Vec::new();
+        for (number, line) in data.lines().enumerate().filter(|(_, line)| !line.trim().is_empty()) {
+            match serde_json::from_str(line) {
+                Ok(record) => records.push(record),
+                Err(e) => warn!("Skipping line {} of the session history: {}", number + 1, e),
+            }
+        }
+
+        Ok(records)
+    }
+
+    /// Records matching every given filter, newest first. A config is matched by its current
+    /// name, or by its guid to include sessions from before it was renamed.
+    pub async fn query(name: Option<&str>, guid: Option<&str>, since: Option<DateTime<Utc>>, limit: Option<usize>) -> Result<Vec<HistoryRecord>> {
+        let mut records = Self::load().await?;
+        records.retain(|record| {
+            let config_matches = name.is_none() || name == Some(record.name.as_str()) || guid == Some(record.guid.as_str());
+            config_matches && since.is_none_or(|since| record.started >= since)
+        });
+        records.reverse();
+        records.truncate(limit.unwrap_or(usize::MAX));
+
+        Ok(records)
+    }
+
+    /// Parses an RFC 3339 timestamp, a local date (YYYY-MM-DD), or a duration before now with an
+    /// `m`, `h`, `d` or `w` suffix.
+    pub fn parse_since(value: &str) -> Result<DateTime<Utc>> {
+        if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+            return Ok(timestamp.to_utc());
+        }
+
+        if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+            return date.and_hms_opt(0, 0, 0)
+                .and_then(|midnight| midnight.and_local_timezone(Local).earliest())
+                .map(|midnight| midnight.to_utc())
+                .ok_or_else(|| anyhow!("The date '{}' does not exist in the local time zone", value));
+        }
+
+        let invalid = || anyhow!("Invalid time '{}', expected an RFC 3339 timestamp, a date (YYYY-MM-DD) or a duration such as 12h or 7d", value);
+        let split = value.len().checked_sub(1).filter(|&split| value.is_char_boundary(split)).ok_or_else(invalid)?;
+        let (amount, unit) = value.split_at(split);
+        let amount = amount.parse::<i64>().ok().filter(|&amount| amount >= 0).ok_or_else(invalid)?;
+        let delta = match unit {
+            "m" => TimeDelta::try_minutes(amount),
+            "h" => TimeDelta::try_hours(amount),
+            "d" => TimeDelta::try_days(amount),
+            "w" => TimeDelta::try_weeks(amount),
+            _ => None,
+        }.ok_or_else(invalid)?;
+
+        Utc::now().checked_sub_signed(delta).ok_or_else(invalid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_timestamps() {
+        let since = History::parse_since("2024-05-01T12:30:00+02:00").unwrap();
+        assert_eq!(since, DateTime::parse_from_rfc3339("2024-05-01T10:30:00Z").unwrap());
+    }
+
+    #[test]
+    fn parses_dates_as_local_midnight() {
+        let since = History::parse_since("2024-05-01").unwrap();
+        let midnight = since.with_timezone(&Local);
+        assert_eq!(midnight.date_naive(), NaiveDate::from_ymd_opt(2024, 5, 1).unwrap());
+        assert_eq!(midnight.time(), chrono::NaiveTime::MIN);
+    }
+
+    #[test]
+    fn parses_durations() {
+        for (value, delta) in [
+            ("30m", TimeDelta::minutes(30)),
+            ("12h", TimeDelta::hours(12)),
+            ("7d", TimeDelta::days(7)),
+            ("1w", TimeDelta::weeks(1)),
+            ("0h", TimeDelta::zero()),
+        ] {
+            let before = Utc::now();
+            let since = History::parse_since(value).unwrap();
+            let after = Utc::now();
+            assert!(since >= before - delta && since <= after - delta, "{}", value);
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_values() {
+        for value in ["", "h", "5", "5x", "-5h", "5 h", "1.5h", "2024-13-01", "9999999999999w", "106751991167d", "ü"] {
+            assert!(History::parse_since(value).is_err(), "{}", value);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use log::{info, warn};
+use nix::unistd::geteuid;
+use tokio::fs;
+use tokio::process::Command;
+use tokio::time::timeout;
+use common::paths::HOOKS_PATH;
+use crate::config::ConfigEntry;
+use crate::openvpn_output::SessionInfo;
+use crate::session::SessionStatus;
+
+/// Runs the executables of the hooks directory and of a config when its session changes status.
+pub struct Hooks {}
+
+impl Hooks {
+    /// Runs every hook for the given status, in order. A failing `Starting` hook stops the
+    /// remaining ones and is returned as an error, so the start can be aborted; failures of
+    /// the other hooks are only logged.
+    pub async fn run(status: &SessionStatus, config: &ConfigEntry, info: &SessionInfo, time_limit: Duration) -> Result<()> {
+        let hooks = Self::list(config).await?;
+        let event = format!("{:?}", status);
+        let environment = Self::environment(&event, config, info);
+
+        for hook in hooks {
+            let result = Self::run_hook(&hook, &event, config, &environment, time_limit).await;
+            match (result, status) {
+                (Ok(_), _) => {}
+                (Err(e), SessionStatus::Starting) => return Err(e),
+                (Err(e), _) => warn!("{}", e),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The executables of the hooks directory sorted by name, followed by the hooks of the config.
+    async fn list(config: &ConfigEntry) -> Result<Vec<PathBuf>> {
+        let mut hooks = Vec::new();
+        if Path::new(HOOKS_PATH).is_dir() {
+            let mut entries = fs::read_dir(HOOKS_PATH).await
+                .with_context(|| format!("Failed to read the hooks directory '{}'", HOOKS_PATH))?;
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let metadata = entry.metadata().await?;
+                // Skip hidden files and editor backups, like run-parts does
+                if name.starts_with('.') || name.ends_with('~') || !metadata.is_file() || metadata.permissions().mode() & 0o111 == 0 {
+                    continue;
+                }
+                hooks.push(entry.path());
+            }
+            hooks.sort();
+        }
+
+        hooks.extend(config.hooks.iter().map(PathBuf::from));
+        Ok(hooks)
+    }
+
+    fn environment(event: &str, config: &ConfigEntry, info: &SessionInfo) -> Vec<(String, String)> {
+        let mut environment = vec![
+            ("OVPN_EVENT".to_string(), event.to_string()),
+            ("OVPN_CONFIG_NAME".to_string(), config.name.clone()),
+            ("OVPN_CONFIG_GUID".to_string(), config.guid.clone()),
+        ];
+
+        let optional = [
+            ("OVPN_TUN_DEVICE", info.tun_device.clone()),
+            ("OVPN_LOCAL_IP", info.local_ip.clone()),
+            ("OVPN_REMOTE_IP", info.remote_ip.clone()),
+            ("OVPN_DNS_SERVERS", Some(info.dns_servers.join(" ")).filter(|v| !v.is_empty())),
+            ("OVPN_SEARCH_DOMAINS", Some(info.search_domains.join(" ")).filter(|v| !v.is_empty())),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                environment.push((name.to_string(), value));
+            }
+        }
+
+        environment
+    }
+
+    async fn run_hook(hook: &Path, event: &str, config: &ConfigEntry, environment: &[(String, String)], time_limit: Duration) -> Result<()> {
+        let name = hook.display();
+        let metadata = fs::metadata(hook).await.with_context(|| format!("Hook '{}' cannot be found", name))?;
+        // Hooks run with the privileges of the daemon, so they must not be replaceable by anyone else
+        let owner = metadata.uid();
+        if (owner != 0 && owner != geteuid().as_raw()) || metadata.mode() & 0o022 != 0 {
+            return Err(anyhow!("Hook '{}' was not run, it must be owned by root or the daemon user and not writable by others", name));
+        }
+
+        let child = Command::new(hook)
+            .arg(event)
+            .envs(environment.iter().map(|(k, v)| (k, v)))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("Failed to run hook '{}'", name))?;
+
+        let output = match timeout(time_limit, child.wait_with_output()).await {
+            Ok(output) => output.with_context(|| format!("Failed to run hook '{}'", name))?,
+            Err(_) => return Err(anyhow!("Hook '{}' did not finish within {} seconds and was killed", name, time_limit.as_secs())),
+        };
+
+        for line in String::from_utf8_lossy(&output.stdout).lines().chain(String::from_utf8_lossy(&output.stderr).lines()) {
+            info!(OVPN_SESSION_GUID = config.guid.as_str(), OVPN_CONFIG_NAME = config.name.as_str(), OVPN_STREAM = "hook"; "HOOK {} >> {}", name, line);
+        }
+
+        if !output.status.success() {
+            return Err(anyhow!("Hook '{}' failed on {} with {}", name, event, output.status));
+        }
+
+        Ok(())
+    }
+}
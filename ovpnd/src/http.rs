@@ -0,0 +1,210 @@
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use std::time::Duration;
+use anyhow::{anyhow, Context, Result};
+use tokio::fs;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::time::timeout;
+use crate::daemon_config::ListenAddress;
+
+/// Largest request head that is accepted.
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+/// Time a client has to send its request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A connection accepted by an `HttpListener`, from a TCP port or a unix socket.
+pub trait Connection: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Connection for T {}
+
+/// The listening socket of the HTTP endpoints of the daemon (metrics and API).
+pub enum HttpListener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl HttpListener {
+    /// Binds the address, replacing a stale unix socket and setting its permissions.
+    pub async fn bind(address: &ListenAddress, mode: u32) -> Result<HttpListener> {
+        match address {
+            ListenAddress::Tcp(address) => Ok(HttpListener::Tcp(TcpListener::bind(address).await
+                .with_context(|| format!("Failed to listen on {}", address))?)),
+            ListenAddress::Unix(path) => {
+                _ = fs::remove_file(path).await;
+                let listener = UnixListener::bind(path).with_context(|| format!("Failed to listen on {}", path))?;
+                fs::set_permissions(path, Permissions::from_mode(mode)).await?;
+                Ok(HttpListener::Unix(listener))
+            }
+        }
+    }
+
+    pub async fn accept(&self) -> std::io::Result<Box<dyn Connection>> {
+        match self {
+            HttpListener::Tcp(listener) => Ok(Box::new(listener.accept().await?.0)),
+            HttpListener::Unix(listener) => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// A parsed HTTP/1.1 request. Path segments and query values are percent-decoded.
+pub struct HttpRequest {
+    pub method: String,
+    pub path: Vec<String>,
+    query: Vec<(String, String)>,
+    headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl HttpRequest {
+    /// Reads the request head, and the body if it has a `Content-Length` of at most `max_body` bytes.
+    pub async fn read(stream: &mut dyn Connection, max_body: usize) -> Result<HttpRequest> {
+        let mut request = Self::read_head(stream).await?;
+        request.read_body(stream, max_body).await?;
+        Ok(request)
+    }
+
+    /// Reads the request head only, so it can be checked before the body is accepted. The body
+    /// is incomplete until `read_body` is called.
+    pub async fn read_head(stream: &mut dyn Connection) -> Result<HttpRequest> {
+        timeout(REQUEST_TIMEOUT, Self::read_request_head(stream)).await
+            .map_err(|_| anyhow!("The request was not received in time"))?
+    }
+
+    /// Reads the rest of the body, if it has a `Content-Length` of at most `max_body` bytes.
+    pub async fn read_body(&mut self, stream: &mut dyn Connection, max_body: usize) -> Result<()> {
+        timeout(REQUEST_TIMEOUT, self.read_request_body(stream, max_body)).await
+            .map_err(|_| anyhow!("The request was not received in time"))?
+    }
+
+    async fn read_request_head(stream: &mut dyn Connection) -> Result<HttpRequest> {
+        let mut data = Vec::new();
+        let mut buffer = [0u8; 4096];
+        let head_end = loop {
+            if let Some(position) = data.windows(4).position(|window| window == b"\r\n\r\n") {
+                break position;
+            }
+            if data.len() > MAX_HEAD_SIZE {
+                return Err(anyhow!("The request head is too large"));
+            }
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                return Err(anyhow!("The connection was closed before the request was complete"));
+            }
+            data.extend_from_slice(&buffer[..read]);
+        };
+
+        let head = String::from_utf8_lossy(&data[..head_end]).to_string();
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+        let method = request_line.next().context("The request line is empty")?.to_string();
+        let target = request_line.next().context("The request target is missing")?;
+        let headers = lines
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_ascii_lowercase(), value.trim().to_string()))
+            .collect::<Vec<_>>();
+
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = path.split('/').filter(|segment| !segment.is_empty()).map(|segment| percent_decode(segment, false)).collect();
+        let query = query.split('&')
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+            .map(|(name, value)| (percent_decode(name, true), percent_decode(value, true)))
+            .collect();
+
+        Ok(HttpRequest { method, path, query, headers, body: data.split_off(head_end + 4) })
+    }
+
+    async fn read_request_body(&mut self, stream: &mut dyn Connection, max_body: usize) -> Result<()> {
+        let length = match self.header("content-length") {
+            Some(length) => length.parse::<usize>().context("Invalid Content-Length")?,
+            None => 0,
+        };
+        if length > max_body {
+            return Err(anyhow!("The request body is larger than {} bytes", max_body));
+        }
+        let mut buffer = [0u8; 4096];
+        while self.body.len() < length {
+            let read = stream.read(&mut buffer).await?;
+            if read == 0 {
+                return Err(anyhow!("The connection was closed before the request body was complete"));
+            }
+            self.body.extend_from_slice(&buffer[..read]);
+        }
+        self.body.truncate(length);
+
+        Ok(())
+    }
+
+    /// Returns the value of a header, names are case-insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str())
+    }
+}
+
+pub struct HttpResponse {
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl HttpResponse {
+    pub fn new(status: &'static str, content_type: &'static str, body: impl Into<Vec<u8>>) -> HttpResponse {
+        Self { status, content_type, body: body.into() }
+    }
+
+    /// Writes the response and closes the connection.
+    pub async fn send(&self, stream: &mut dyn Connection) -> Result<()> {
+        let head = format!("HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status, self.content_type, self.body.len());
+        stream.write_all(head.as_bytes()).await?;
+        stream.write_all(&self.body).await?;
+        stream.shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Decodes `%XX` escapes, and `+` if the value is part of a query string. Invalid escapes are kept.
+fn percent_decode(value: &str, query: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes.get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (escaped, bytes[index]) {
+            (Some(byte), _) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            (None, b'+') if query => {
+                decoded.push(b' ');
+                index += 1;
+            }
+            (None, byte) => {
+                decoded.push(byte);
+                index += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+/// Compares the token without returning early, so its content cannot be guessed from timing.
+pub fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    if left.len() != right.len() {
+        return false;
+    }
+
+    left.iter().zip(right).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
@@ -0,0 +1,706 @@
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Component, Path};
+use anyhow::{anyhow, Context, Result};
+use flate2::read::GzDecoder;
+use serde::Serialize;
+use tokio::fs;
+use tokio::io::AsyncReadExt;
+use common::command::ConflictStrategy;
+use crate::bundle::Bundle;
+use crate::config::{ConfigEntry, ConfigKind, ConfigManager};
+use crate::daemon_config::DaemonConfig;
+use crate::network_manager::NetworkManager;
+use crate::routes::RoutePolicy;
+
+/// File extensions that are picked up when importing a directory or an archive.
+const CONFIG_EXTENSIONS: [&str; 3] = ["ovpn", "conf", NM_EXTENSION];
+
+/// NetworkManager keyfiles, which are translated into an equivalent config before importing.
+const NM_EXTENSION: &str = "nmconnection";
+
+/// Files larger than this are not read, so a crafted archive cannot exhaust the memory.
+const MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// A single configuration file read from the import path.
+pub struct ImportSource {
+    pub file: String,
+    pub stem: String,
+    pub data: Vec<u8>,
+
+    /// Index metadata restored from an exported bundle.
+    pub metadata: Option<ConfigEntry>,
+
+    /// Problems found while translating the source, e.g. settings that could not be mapped.
+    pub warnings: Vec<String>,
+
+    /// Why the file could not be read, it is reported as failed instead of being imported.
+    pub error: Option<String>,
+}
+
+impl ImportSource {
+    fn failed(file: &str, error: String) -> ImportSource {
+        ImportSource {
+            file: file.to_string(),
+            stem: Importer::file_stem(file).unwrap_or_default(),
+            data: Vec::new(),
+            metadata: None,
+            warnings: Vec::new(),
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub enum ImportAction {
+    Imported,
+    Renamed,
+    Overwritten,
+    Skipped,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportResult {
+    pub file: String,
+    pub name: String,
+    pub action: ImportAction,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub results: Vec<ImportResult>,
+}
+
+pub struct Importer {}
+
+impl Importer {
+    /// Returns true if the path refers to more than one configuration (a directory or an archive).
+    pub fn is_bulk(path: &str) -> bool {
+        Path::new(path).is_dir() || Self::is_zip(path) || Self::is_tar_gz(path)
+    }
+
+    /// Reads every importable file from `path`, which can be a single file, a directory or an archive.
+    pub async fn read_sources(path: &str) -> Result<Vec<ImportSource>> {
+        let sources = if Path::new(path).is_dir() {
+            Self::read_directory(path).await?
+        } else if Self::is_zip(path) || Self::is_tar_gz(path) {
+            let archive_path = path.to_string();
+            tokio::task::spawn_blocking(move || Self::read_archive(&archive_path)).await??
+        } else {
+            let data = Self::read_file(Path::new(path)).await.context("Cannot read the configuration file")?;
+            vec![ImportSource {
+                file: path.to_string(),
+                stem: Self::file_stem(path).context("Invalid configuration file name")?,
+                data,
+                metadata: None,
+                warnings: Vec::new(),
+                error: None,
+            }]
+        };
+
+        let bulk = Self::is_bulk(path);
+        let mut translated = Vec::new();
+        for mut source in sources {
+            if source.error.is_some() || !source.file.to_lowercase().ends_with(&format!(".{}", NM_EXTENSION)) {
+                translated.push(source);
+                continue;
+            }
+
+            // Directories like /etc/NetworkManager/system-connections also contain non-VPN connections
+            match NetworkManager::translate(&String::from_utf8_lossy(&source.data)) {
+                Ok(Some(translation)) => {
+                    source.stem = translation.name.unwrap_or(source.stem);
+                    source.data = translation.config.into_bytes();
+                    source.warnings = translation.warnings;
+                    translated.push(source);
+                }
+                Ok(None) if bulk => continue,
+                Ok(None) => return Err(anyhow!("The keyfile does not describe an OpenVPN connection")),
+                Err(e) if bulk => {
+                    source.error = Some(e.to_string());
+                    translated.push(source);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        translated.sort_by(|a, b| a.file.cmp(&b.file));
+        Ok(translated)
+    }
+
+    /// Imports the sources one by one. A failure only affects the file that caused it.
+    /// In dry-run mode the outcome of every file is computed, but nothing is written.
+    pub async fn import(config_manager: &mut ConfigManager, daemon_config: &DaemonConfig, sources: Vec<ImportSource>, names: Vec<String>,
+                        conflict: Option<ConflictStrategy>, dry_run: bool) -> ImportReport {
+        let mut results = Vec::new();
+        let mut batch_names = HashSet::new();
+
+        for (source, name) in sources.into_iter().zip(names) {
+            if let Some(error) = source.error {
+                results.push(ImportResult { file: source.file, name, action: ImportAction::Failed, error: Some(error), warnings: source.warnings });
+                continue;
+            }
+
+            let mut warnings = source.warnings;
+            let metadata = source.metadata.map(|mut metadata| {
+                warnings.extend(Self::apply_policy(&mut metadata, daemon_config));
+                metadata
+            });
+
+            // Two files of the same import never replace each other
+            let in_batch = batch_names.contains(&name);
+            let exists = |n: &str| config_manager.has_name(n) || batch_names.contains(n);
+            let (name, action) = match (exists(&name), conflict) {
+                (false, _) => (name, ImportAction::Imported),
+                (true, Some(ConflictStrategy::Skip)) => (name, ImportAction::Skipped),
+                (true, Some(ConflictStrategy::Overwrite)) if !in_batch => (name, ImportAction::Overwritten),
+                (true, Some(ConflictStrategy::Rename)) => {
+                    let mut counter = 2;
+                    while exists(&format!("{}-{}", name, counter)) {
+                        counter += 1;
+                    }
+                    (format!("{}-{}", name, counter), ImportAction::Renamed)
+                }
+                (true, _) => {
+                    let error = match in_batch {
+                        true => "Another file of the import has the same name",
+                        false => "A configuration with the same name already exists",
+                    };
+                    results.push(ImportResult {
+                        file: source.file,
+                        name,
+                        action: ImportAction::Failed,
+                        error: Some(error.to_string()),
+                        warnings,
+                    });
+                    continue;
+                }
+            };
+
+            batch_names.insert(name.clone());
+            if dry_run || action == ImportAction::Skipped {
+                results.push(ImportResult { file: source.file, name, action, error: None, warnings });
+                continue;
+            }
+
+            let stored = match config_manager.store(&source.data, name.clone()).await {
+                Ok(entry) => match metadata {
+                    Some(metadata) => config_manager.update(&entry.name, |entry| Self::restore_metadata(entry, metadata)).await,
+                    None => Ok(entry),
+                },
+                Err(e) => Err(e),
+            };
+
+            match stored {
+                Ok(_) => results.push(ImportResult { file: source.file, name, action, error: None, warnings }),
+                Err(e) => results.push(ImportResult {
+                    file: source.file,
+                    name,
+                    action: ImportAction::Failed,
+                    error: Some(e.to_string()),
+                    warnings,
+                }),
+            }
+        }
+
+        ImportReport { dry_run, results }
+    }
+
+    /// Copies the settings of a bundled entry, keeping the name and GUID it was stored under.
+    /// Bundles of profiles contain the rendered config, so they are restored as regular configs.
+    fn restore_metadata(entry: &mut ConfigEntry, metadata: ConfigEntry) {
+        let (name, guid) = (entry.name.clone(), entry.guid.clone());
+        *entry = metadata;
+        entry.name = name;
+        entry.guid = guid;
+        if entry.kind == ConfigKind::Profile {
+            entry.kind = ConfigKind::Config;
+            entry.template = None;
+            entry.variables.clear();
+        }
+    }
+
+    /// Drops the settings of a bundled entry that the daemon policy does not allow, since the
+    /// bundle may come from another machine. Returns a warning for every dropped setting.
+    fn apply_policy(metadata: &mut ConfigEntry, daemon_config: &DaemonConfig) -> Vec<String> {
+        let mut warnings = Vec::new();
+        let mut allowed = |setting: String, probe: ConfigEntry| match probe.validate(daemon_config) {
+            Ok(_) => true,
+            Err(e) => {
+                warnings.push(format!("The bundled {} was not restored: {}", setting, e));
+                false
+            }
+        };
+
+        metadata.arguments.retain(|argument| {
+            allowed(format!("argument '{}'", argument), ConfigEntry { arguments: vec![argument.clone()], ..Default::default() })
+        });
+        metadata.environment.retain(|name, value| {
+            let environment = [(name.clone(), value.clone())].into();
+            allowed(format!("environment variable '{}'", name), ConfigEntry { environment, ..Default::default() })
+        });
+        metadata.hooks.retain(|hook| {
+            allowed(format!("hook '{}'", hook), ConfigEntry { hooks: vec![hook.clone()], ..Default::default() })
+        });
+        if !allowed("route policy".to_string(), ConfigEntry { routes: metadata.routes.clone(), ..Default::default() }) {
+            metadata.routes = RoutePolicy::default();
+        }
+        if metadata.kill_switch && !allowed("kill switch".to_string(), ConfigEntry { kill_switch: true, routes: metadata.routes.clone(), ..Default::default() }) {
+            metadata.kill_switch = false;
+        }
+
+        warnings
+    }
+
+    /// Reads the config files of a directory. Files that cannot be read are returned with an error,
+    /// so the others are still imported.
+    async fn read_directory(path: &str) -> Result<Vec<ImportSource>> {
+        let mut sources = Vec::new();
+        let mut directory = fs::read_dir(path).await?;
+        loop {
+            let file = match directory.next_entry().await {
+                Ok(Some(file)) => file,
+                Ok(None) => break,
+                Err(e) => {
+                    sources.push(ImportSource::failed(path, format!("Cannot read the directory: {}", e)));
+                    break;
+                }
+            };
+
+            let file_path = file.path().to_string_lossy().to_string();
+            if !Self::is_config_file(&file.file_name().to_string_lossy()) {
+                continue;
+            }
+            match file.file_type().await {
+                Ok(file_type) if !file_type.is_file() => continue,
+                Ok(_) => {}
+                Err(e) => {
+                    sources.push(ImportSource::failed(&file_path, format!("Cannot read the file type: {}", e)));
+                    continue;
+                }
+            }
+
+            match Self::read_file(&file.path()).await {
+                Ok(data) => sources.push(ImportSource {
+                    file: file_path.clone(),
+                    stem: Self::file_stem(&file_path).unwrap_or_default(),
+                    data,
+                    metadata: None,
+                    warnings: Vec::new(),
+                    error: None,
+                }),
+                Err(e) => sources.push(ImportSource::failed(&file_path, e.to_string())),
+            }
+        }
+
+        Ok(sources)
+    }
+
+    fn read_archive(path: &str) -> Result<Vec<ImportSource>> {
+        let mut sources = Vec::new();
+        let file = File::open(path).context("Cannot open the archive")?;
+
+        if Self::is_zip(path) {
+            let mut archive = zip::ZipArchive::new(file)?;
+            for i in 0..archive.len() {
+                let mut entry = archive.by_index(i)?;
+                let entry_name = entry.name().to_string();
+                if !entry.is_file() || !Self::is_config_file(&entry_name) {
+                    continue;
+                }
+
+                match Self::read_limited(&mut entry) {
+                    Ok(data) => sources.push(ImportSource {
+                        stem: Self::file_stem(&entry_name).unwrap_or_default(),
+                        file: entry_name,
+                        data,
+                        metadata: None,
+                        warnings: Vec::new(),
+                        error: None,
+                    }),
+                    Err(e) => sources.push(ImportSource::failed(&entry_name, e.to_string())),
+                }
+            }
+        } else if let Some(bundle) = Bundle::read(path)? {
+            sources.push(ImportSource {
+                file: path.to_string(),
+                stem: bundle.metadata.entry.name.clone(),
+                data: bundle.config.into_bytes(),
+                metadata: Some(bundle.metadata.entry),
+                warnings: Vec::new(),
+                error: None,
+            });
+        } else {
+            let mut archive = tar::Archive::new(GzDecoder::new(file));
+            for entry in archive.entries()? {
+                let mut entry = entry?;
+                let entry_name = entry.path()?.to_string_lossy().to_string();
+                if !entry.header().entry_type().is_file() || !Self::is_config_file(&entry_name) {
+                    continue;
+                }
+
+                match Self::read_limited(&mut entry) {
+                    Ok(data) => sources.push(ImportSource {
+                        stem: Self::file_stem(&entry_name).unwrap_or_default(),
+                        file: entry_name,
+                        data,
+                        metadata: None,
+                        warnings: Vec::new(),
+                        error: None,
+                    }),
+                    Err(e) => sources.push(ImportSource::failed(&entry_name, e.to_string())),
+                }
+            }
+        }
+
+        if sources.is_empty() {
+            return Err(anyhow!("The archive does not contain any .ovpn, .conf or .nmconnection files"));
+        }
+
+        Ok(sources)
+    }
+
+    /// Reads at most [`MAX_FILE_SIZE`] bytes, failing if there is more.
+    pub fn read_limited(reader: impl Read) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        reader.take(MAX_FILE_SIZE + 1).read_to_end(&mut data)?;
+        Self::check_size(data)
+    }
+
+    async fn read_file(path: &Path) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        fs::File::open(path).await?.take(MAX_FILE_SIZE + 1).read_to_end(&mut data).await?;
+        Self::check_size(data)
+    }
+
+    fn check_size(data: Vec<u8>) -> Result<Vec<u8>> {
+        match data.len() as u64 > MAX_FILE_SIZE {
+            true => Err(anyhow!("The file is larger than {} KiB", MAX_FILE_SIZE / 1024)),
+            false => Ok(data),
+        }
+    }
+
+    /// Matches `.ovpn`, `.conf` and `.nmconnection` files, ignoring hidden files and macOS resource forks.
+    fn is_config_file(path: &str) -> bool {
+        let path = Path::new(path);
+        let hidden = path.components().any(|c| match c {
+            Component::Normal(part) => part.to_string_lossy().starts_with('.') || part == "__MACOSX",
+            _ => false,
+        });
+        let extension = path.extension().and_then(|e| e.to_str()).unwrap_or_default().to_lowercase();
+        !hidden && CONFIG_EXTENSIONS.contains(&extension.as_str())
+    }
+
+    fn file_stem(path: &str) -> Option<String> {
+        Path::new(path).file_stem().and_then(|s| s.to_str()).map(|s| s.to_string())
+    }
+
+    fn is_zip(path: &str) -> bool {
+        path.to_lowercase().ends_with(".zip")
+    }
+
+    fn is_tar_gz(path: &str) -> bool {
+        let path = path.to_lowercase();
+        path.ends_with(".tar.gz") || path.ends_with(".tgz")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use crate::config::tests::TestDirectory;
+    use super::*;
+
+    const CONFIG: &str = "client\nremote vpn.example.com 1194\n";
+
+    fn source(file: &str, data: &str) -> ImportSource {
+        ImportSource {
+            file: file.to_string(),
+            stem: Importer::file_stem(file).unwrap(),
+            data: data.as_bytes().to_vec(),
+            metadata: None,
+            warnings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// Imports one source per name into a store that already contains `work`.
+    async fn import(names: &[&str], conflict: Option<ConflictStrategy>, dry_run: bool) -> (TestDirectory, ConfigManager, ImportReport) {
+        let store = TestDirectory::store();
+        let mut config_manager = ConfigManager::new().await.unwrap();
+        config_manager.store(b"existing\n", "work".to_string()).await.unwrap();
+
+        let sources = names.iter().map(|name| source(&format!("{}.ovpn", name), CONFIG)).collect();
+        let names = names.iter().map(|name| name.to_string()).collect();
+        let report = Importer::import(&mut config_manager, &DaemonConfig::default(), sources, names, conflict, dry_run).await;
+        (store, config_manager, report)
+    }
+
+    fn outcomes(report: &ImportReport) -> Vec<(&str, ImportAction)> {
+        report.results.iter().map(|r| (r.name.as_str(), r.action.clone())).collect()
+    }
+
+    fn names(config_manager: &ConfigManager) -> Vec<&str> {
+        config_manager.get_index().get_entries().iter().map(|e| e.name.as_str()).collect()
+    }
+
+    async fn stored_text(config_manager: &ConfigManager, name: &str) -> String {
+        let entry = config_manager.get_by_name(name.to_string()).await.unwrap();
+        ConfigManager::get_config_text(&entry).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn reads_the_config_files_of_a_directory() {
+        let directory = TestDirectory::new("import");
+        for file in ["b.conf", "a.ovpn", ".hidden.ovpn", "notes.txt"] {
+            std::fs::write(directory.file(file), CONFIG).unwrap();
+        }
+        std::fs::create_dir(directory.file("nested.ovpn")).unwrap();
+        std::fs::write(directory.file("large.ovpn"), vec![b'#'; MAX_FILE_SIZE as usize + 1]).unwrap();
+
+        let sources = Importer::read_sources(&directory.file("")).await.unwrap();
+
+        let read = sources.iter().map(|s| (s.stem.as_str(), s.error.is_some())).collect::<Vec<_>>();
+        assert_eq!(read, [("a", false), ("b", false), ("large", true)]);
+        assert_eq!(sources[0].data, CONFIG.as_bytes());
+        assert!(Importer::is_bulk(&directory.file("")));
+    }
+
+    #[tokio::test]
+    async fn reads_the_config_files_of_a_zip_archive() {
+        let directory = TestDirectory::new("import");
+        let path = directory.file("configs.zip");
+        let mut archive = zip::ZipWriter::new(File::create(&path).unwrap());
+        for file in ["office/work.ovpn", "__MACOSX/office/._work.ovpn", "home.conf", "README"] {
+            archive.start_file(file, zip::write::SimpleFileOptions::default()).unwrap();
+            archive.write_all(CONFIG.as_bytes()).unwrap();
+        }
+        archive.finish().unwrap();
+
+        let sources = Importer::read_sources(&path).await.unwrap();
+
+        let read = sources.iter().map(|s| (s.file.as_str(), s.stem.as_str())).collect::<Vec<_>>();
+        assert_eq!(read, [("home.conf", "home"), ("office/work.ovpn", "work")]);
+        assert!(sources.iter().all(|s| s.data == CONFIG.as_bytes()));
+    }
+
+    #[tokio::test]
+    async fn reads_the_config_files_of_a_tar_archive() {
+        let directory = TestDirectory::new("import");
+        let path = directory.file("configs.tar.gz");
+        let mut builder = tar::Builder::new(GzEncoder::new(File::create(&path).unwrap(), Compression::default()));
+        for file in ["office/work.ovpn", "office/.work.ovpn.swp", "home.ovpn"] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(CONFIG.len() as u64);
+            header.set_mode(0o600);
+            header.set_cksum();
+            builder.append_data(&mut header, file, CONFIG.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap();
+
+        let sources = Importer::read_sources(&path).await.unwrap();
+
+        let read = sources.iter().map(|s| s.stem.as_str()).collect::<Vec<_>>();
+        assert_eq!(read, ["home", "work"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_an_archive_without_configs() {
+        let directory = TestDirectory::new("import");
+        let path = directory.file("empty.zip");
+        let mut archive = zip::ZipWriter::new(File::create(&path).unwrap());
+        archive.start_file("README", zip::write::SimpleFileOptions::default()).unwrap();
+        archive.finish().unwrap();
+
+        assert!(Importer::read_sources(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn fails_on_an_existing_name_by_default() {
+        let (_store, config_manager, report) = import(&["work", "home"], None, false).await;
+
+        assert_eq!(outcomes(&report), [("work", ImportAction::Failed), ("home", ImportAction::Imported)]);
+        assert_eq!(report.results[0].error.as_deref(), Some("A configuration with the same name already exists"));
+        assert_eq!(stored_text(&config_manager, "work").await, "existing\n");
+        assert_eq!(names(&config_manager), ["work", "home"]);
+    }
+
+    #[tokio::test]
+    async fn skips_existing_names() {
+        let (_store, config_manager, report) = import(&["work"], Some(ConflictStrategy::Skip), false).await;
+
+        assert_eq!(outcomes(&report), [("work", ImportAction::Skipped)]);
+        assert_eq!(stored_text(&config_manager, "work").await, "existing\n");
+    }
+
+    #[tokio::test]
+    async fn renames_existing_names() {
+        let (_store, config_manager, report) = import(&["work", "work"], Some(ConflictStrategy::Rename), false).await;
+
+        assert_eq!(outcomes(&report), [("work-2", ImportAction::Renamed), ("work-3", ImportAction::Renamed)]);
+        assert_eq!(stored_text(&config_manager, "work").await, "existing\n");
+        assert_eq!(stored_text(&config_manager, "work-2").await, CONFIG);
+        assert_eq!(names(&config_manager), ["work", "work-2", "work-3"]);
+    }
+
+    #[tokio::test]
+    async fn overwrites_existing_names_once_per_import() {
+        let (_store, config_manager, report) = import(&["work", "work"], Some(ConflictStrategy::Overwrite), false).await;
+
+        assert_eq!(outcomes(&report), [("work", ImportAction::Overwritten), ("work", ImportAction::Failed)]);
+        assert_eq!(report.results[1].error.as_deref(), Some("Another file of the import has the same name"));
+        assert_eq!(stored_text(&config_manager, "work").await, CONFIG);
+        assert_eq!(names(&config_manager), ["work"]);
+    }
+
+    #[tokio::test]
+    async fn reports_failed_sources_without_importing_them() {
+        let _store = TestDirectory::store();
+        let mut config_manager = ConfigManager::new().await.unwrap();
+        let sources = vec![ImportSource::failed("broken.ovpn", "Cannot read".to_string()), source("home.ovpn", CONFIG)];
+
+        let report = Importer::import(&mut config_manager, &DaemonConfig::default(), sources, vec!["broken".to_string(), "home".to_string()], None, false).await;
+
+        assert_eq!(outcomes(&report), [("broken", ImportAction::Failed), ("home", ImportAction::Imported)]);
+        assert_eq!(names(&config_manager), ["home"]);
+    }
+
+    #[tokio::test]
+    async fn dry_run_reports_without_writing() {
+        let (store, config_manager, report) = import(&["work", "home", "home"], Some(ConflictStrategy::Rename), true).await;
+
+        assert!(report.dry_run);
+        assert_eq!(outcomes(&report), [("work-2", ImportAction::Renamed), ("home", ImportAction::Imported), ("home-2", ImportAction::Renamed)]);
+        assert_eq!(names(&config_manager), ["work"]);
+        assert_eq!(ConfigManager::new().await.unwrap().get_index().get_entries().len(), 1);
+        let files = std::fs::read_dir(store.file("")).unwrap().count();
+        assert_eq!(files, 2);
+    }
+
+    /// Stores a config referencing a CA, a static key and a credentials file, with settings of every kind.
+    async fn create_bundled_config(files: &TestDirectory, config_manager: &mut ConfigManager) -> Box<ConfigEntry> {
+        std::fs::write(files.file("ca.crt"), "-----BEGIN CERTIFICATE-----\nCA\n-----END CERTIFICATE-----\n").unwrap();
+        std::fs::write(files.file("ta.key"), "static key\n").unwrap();
+        std::fs::write(files.file("auth.txt"), "user\npassword\n").unwrap();
+        let text = format!("client\nca {}\ntls-auth {} 1\nauth-user-pass {}\n", files.file("ca.crt"), files.file("ta.key"), files.file("auth.txt"));
+        config_manager.store(text.as_bytes(), "work".to_string()).await.unwrap();
+
+        config_manager.update("work", |entry| {
+            entry.arguments = vec!["--verb 4".to_string(), "--up /tmp/up.sh".to_string()];
+            entry.environment = [("LANG".to_string(), "C".to_string()), ("LD_PRELOAD".to_string(), "/tmp/x.so".to_string())].into();
+            entry.routes = RoutePolicy { mode: common::command::RouteMode::Include, targets: vec!["10.0.0.0/8".to_string()] };
+            entry.kill_switch = true;
+            entry.hooks = vec!["/usr/local/bin/notify".to_string()];
+            entry.autostart = true;
+        }).await.unwrap()
+    }
+
+    /// Exports the config as a bundle and imports it again as `copy`.
+    async fn round_trip(include_credentials: bool) -> (String, ImportResult, ConfigEntry) {
+        let _store = TestDirectory::store();
+        let files = TestDirectory::new("bundle");
+        let mut config_manager = ConfigManager::new().await.unwrap();
+        let entry = create_bundled_config(&files, &mut config_manager).await;
+
+        let path = files.file("work.tar.gz");
+        std::fs::write(&path, Bundle::create(&entry, include_credentials).await.unwrap()).unwrap();
+        let sources = Importer::read_sources(&path).await.unwrap();
+        let mut report = Importer::import(&mut config_manager, &DaemonConfig::default(), sources, vec!["copy".to_string()], None, false).await;
+
+        let copy = config_manager.get_by_name("copy".to_string()).await.unwrap();
+        assert_ne!(copy.guid, entry.guid);
+        (ConfigManager::get_config_text(&copy).await.unwrap(), report.results.remove(0), *copy)
+    }
+
+    #[tokio::test]
+    async fn bundles_inline_the_referenced_files() {
+        let (text, result, _) = round_trip(false).await;
+
+        assert_eq!(result.action, ImportAction::Imported);
+        assert!(text.contains("<ca>\n-----BEGIN CERTIFICATE-----\nCA\n-----END CERTIFICATE-----\n</ca>\n"));
+        assert!(text.contains("key-direction 1\n<tls-auth>\nstatic key\n</tls-auth>\n"));
+        assert!(text.contains("\nauth-user-pass /"), "credentials are only bundled on request");
+        assert!(!text.contains("password"));
+    }
+
+    #[tokio::test]
+    async fn bundles_include_credentials_on_request() {
+        let (text, _, _) = round_trip(true).await;
+
+        assert!(text.contains("<auth-user-pass>\nuser\npassword\n</auth-user-pass>\n"));
+        assert!(!text.contains("auth-user-pass /"));
+    }
+
+    #[tokio::test]
+    async fn bundles_restore_the_allowed_settings() {
+        let (_, result, copy) = round_trip(false).await;
+
+        assert_eq!(copy.name, "copy");
+        assert_eq!(copy.arguments, ["--verb 4"]);
+        assert_eq!(copy.environment.keys().collect::<Vec<_>>(), ["LANG"]);
+        assert_eq!(copy.routes.targets, ["10.0.0.0/8"]);
+        assert!(copy.kill_switch && copy.autostart);
+        assert!(copy.hooks.is_empty());
+        assert_eq!(result.warnings, [
+            "The bundled argument '--up /tmp/up.sh' was not restored: The directive '--up' is not allowed by the daemon policy",
+            "The bundled environment variable 'LD_PRELOAD' was not restored: The environment variable 'LD_PRELOAD' is not allowed by the daemon policy",
+            "The bundled hook '/usr/local/bin/notify' was not restored: Per-config hooks are not allowed by the daemon configuration",
+        ]);
+    }
+
+    const KEYFILE: &str = "[connection]\nid=Office VPN\n\n[vpn]\nservice-type=org.freedesktop.NetworkManager.openvpn\nremote=vpn.example.com\nusername=alice\n";
+
+    #[tokio::test]
+    async fn translates_keyfiles_of_a_directory() {
+        let directory = TestDirectory::new("import");
+        std::fs::write(directory.file("office.nmconnection"), KEYFILE).unwrap();
+        std::fs::write(directory.file("wifi.nmconnection"), "[connection]\nid=Home\ntype=wifi\n").unwrap();
+        std::fs::write(directory.file("broken.nmconnection"), "[vpn\nservice-type=org.freedesktop.NetworkManager.openvpn\n").unwrap();
+
+        let sources = Importer::read_sources(&directory.file("")).await.unwrap();
+
+        let read = sources.iter().map(|s| (s.stem.as_str(), s.error.is_some())).collect::<Vec<_>>();
+        assert_eq!(read, [("broken", true), ("Office-VPN", false)]);
+        assert_eq!(String::from_utf8_lossy(&sources[1].data), "client\nnobind\nremote vpn.example.com 1194 udp\ndev tun\n");
+        assert_eq!(sources[1].warnings, ["The username 'alice' is not imported, put it into an auth-user-pass credentials file"]);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_single_keyfile_of_another_connection() {
+        let directory = TestDirectory::new("import");
+        let path = directory.file("wifi.nmconnection");
+        std::fs::write(&path, "[connection]\nid=Home\ntype=wifi\n").unwrap();
+
+        let error = Importer::read_sources(&path).await.err().unwrap();
+
+        assert_eq!(error.to_string(), "The keyfile does not describe an OpenVPN connection");
+    }
+
+    #[tokio::test]
+    async fn keeps_the_translation_warnings_when_importing() {
+        let directory = TestDirectory::new("import");
+        let path = directory.file("office.nmconnection");
+        std::fs::write(&path, KEYFILE).unwrap();
+        let _store = TestDirectory::store();
+        let mut config_manager = ConfigManager::new().await.unwrap();
+
+        let sources = Importer::read_sources(&path).await.unwrap();
+        let names = sources.iter().map(|source| source.stem.clone()).collect();
+        let report = Importer::import(&mut config_manager, &DaemonConfig::default(), sources, names, None, false).await;
+
+        assert_eq!(outcomes(&report), [("Office-VPN", ImportAction::Imported)]);
+        assert_eq!(report.results[0].warnings.len(), 1);
+        assert!(stored_text(&config_manager, "Office-VPN").await.starts_with("client\nnobind\nremote vpn.example.com"));
+    }
+}
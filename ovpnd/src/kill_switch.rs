@@ -0,0 +1,289 @@
+use std::fmt::Write;
+use std::net::IpAddr;
+use anyhow::{anyhow, Context, Result};
+use tokio::net::lookup_host;
+use crate::config::{ConfigEntry, ConfigManager};
+use crate::system::run;
+
+/// The nftables table holding the kill switch rules. Only one session can be active at a time,
+/// so a single table is enough and installing it again replaces the previous rules.
+const TABLE_FAMILY: &str = "inet";
+const TABLE_NAME: &str = "ovpnd_killswitch";
+
+const DEFAULT_PORT: u16 = 1194;
+
+/// Longest interface name the kernel accepts, IFNAMSIZ without the terminating NUL.
+const MAX_DEVICE_NAME: usize = 15;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Protocol {
+    Udp,
+    Tcp,
+}
+
+/// A `remote` of the config, before host names are resolved.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Remote {
+    pub host: String,
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+/// A VPN server address that stays reachable outside the tunnel.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Endpoint {
+    pub address: IpAddr,
+    pub port: u16,
+    pub protocol: Protocol,
+}
+
+/// Blocks all outgoing traffic except loopback, the tunnel and the VPN servers of a config.
+pub struct KillSwitch {
+    pub endpoints: Vec<Endpoint>,
+
+    /// Interface name of the tunnel, possibly an nftables wildcard such as `tun*`.
+    pub device: String,
+
+    /// Set when some remotes are host names. openvpn cannot resolve them by itself once the
+    /// rules are installed, so the resolved addresses are passed to it as extra remotes.
+    resolved_hosts: bool,
+}
+
+impl KillSwitch {
+    /// Collects the endpoints and tun device of a config, resolving host names.
+    pub async fn for_config(entry: &ConfigEntry) -> Result<KillSwitch> {
+        let mut directives = ConfigManager::get_config_text(entry).await?;
+        for argument in &entry.arguments {
+            directives.push('\n');
+            directives.push_str(argument.trim_start_matches("--"));
+        }
+
+        let remotes = Self::parse_remotes(&directives);
+        if remotes.is_empty() {
+            return Err(anyhow!("The kill switch needs at least one 'remote' in the config"));
+        }
+
+        let mut endpoints = Vec::new();
+        for remote in &remotes {
+            let addresses = lookup_host((remote.host.as_str(), remote.port)).await
+                .with_context(|| format!("Failed to resolve the remote '{}'", remote.host))?;
+            for address in addresses {
+                let endpoint = Endpoint { address: address.ip(), port: remote.port, protocol: remote.protocol };
+                if !endpoints.contains(&endpoint) {
+                    endpoints.push(endpoint);
+                }
+            }
+        }
+
+        Ok(KillSwitch {
+            endpoints,
+            device: Self::parse_device(&directives),
+            resolved_hosts: !directives.contains("<connection>") && remotes.iter().any(|r| r.host.parse::<IpAddr>().is_err()),
+        })
+    }
+
+    /// Reads the `remote` directives, applying the global `port`/`rport` and `proto` defaults.
+    pub fn parse_remotes(directives: &str) -> Vec<Remote> {
+        let mut default_port = DEFAULT_PORT;
+        let mut default_protocol = Protocol::Udp;
+        let mut remotes = Vec::new();
+
+        for line in directives.lines() {
+            let tokens = line.split_whitespace().collect::<Vec<_>>();
+            match tokens.as_slice() {
+                ["port" | "rport", port, ..] => default_port = port.parse().unwrap_or(default_port),
+                ["proto", protocol, ..] => default_protocol = Self::parse_protocol(protocol),
+                ["remote", host, rest @ ..] => remotes.push((host.to_string(), rest.first().and_then(|p| p.parse().ok()), rest.get(1).map(|p| Self::parse_protocol(p)))),
+                _ => {}
+            }
+        }
+
+        remotes.into_iter()
+            .map(|(host, port, protocol)| Remote {
+                host,
+                port: port.unwrap_or(default_port),
+                protocol: protocol.unwrap_or(default_protocol),
+            })
+            .collect()
+    }
+
+    /// Returns the interface name set by `dev`, or a wildcard if openvpn picks the number.
+    pub fn parse_device(directives: &str) -> String {
+        let device = directives.lines()
+            .filter_map(|line| line.trim().strip_prefix("dev "))
+            .next_back()
+            .map(|device| device.trim())
+            .unwrap_or("tun");
+
+        match device {
+            "tun" | "tap" => format!("{}*", device),
+            device => device.to_string(),
+        }
+    }
+
+    fn parse_protocol(protocol: &str) -> Protocol {
+        match protocol.starts_with("tcp") {
+            true => Protocol::Tcp,
+            false => Protocol::Udp,
+        }
+    }
+
+    /// Generates the nftables ruleset. The table is created and deleted first, so loading the
+    /// ruleset replaces any previous version atomically.
+    pub fn ruleset(&self) -> Result<String> {
+        Self::check_device(&self.device)?;
+        let table = format!("{} {}", TABLE_FAMILY, TABLE_NAME);
+        let mut ruleset = String::new();
+        let _ = writeln!(ruleset, "table {}", table);
+        let _ = writeln!(ruleset, "delete table {}", table);
+        let _ = writeln!(ruleset, "table {} {{", table);
+        let _ = writeln!(ruleset, "\tchain output {{");
+        let _ = writeln!(ruleset, "\t\ttype filter hook output priority 0; policy drop;");
+        let _ = writeln!(ruleset, "\t\toifname \"lo\" accept");
+        let _ = writeln!(ruleset, "\t\toifname \"{}\" accept", self.device);
+        // Keep DHCP leases and IPv6 neighbor discovery working on the physical interfaces
+        let _ = writeln!(ruleset, "\t\tudp sport 68 udp dport 67 accept");
+        let _ = writeln!(ruleset, "\t\tudp sport 546 udp dport 547 accept");
+        let _ = writeln!(ruleset, "\t\ticmpv6 type {{ nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert }} accept");
+        for endpoint in &self.endpoints {
+            let family = if endpoint.address.is_ipv4() { "ip" } else { "ip6" };
+            let protocol = match endpoint.protocol {
+                Protocol::Udp => "udp",
+                Protocol::Tcp => "tcp",
+            };
+            let _ = writeln!(ruleset, "\t\t{} daddr {} {} dport {} accept", family, endpoint.address, protocol, endpoint.port);
+        }
+        let _ = writeln!(ruleset, "\t}}");
+        let _ = writeln!(ruleset, "}}");
+
+        Ok(ruleset)
+    }
+
+    /// Checks that the device is an interface name the kernel accepts, optionally followed by a
+    /// `*` wildcard, so it cannot break out of the quoted string in the ruleset.
+    fn check_device(device: &str) -> Result<()> {
+        let name = device.strip_suffix('*').unwrap_or(device);
+        let valid = !name.is_empty()
+            && name.len() <= MAX_DEVICE_NAME
+            && name != "." && name != ".."
+            && name.chars().all(|c| c.is_ascii_graphic() && !matches!(c, '/' | ':' | '"' | '\\' | '*'));
+        match valid {
+            true => Ok(()),
+            false => Err(anyhow!("Invalid tun device name '{}' for the kill switch", device.escape_debug())),
+        }
+    }
+
+    /// Extra remotes for openvpn, placed before `--config` so they are tried first.
+    pub fn to_arguments(&self) -> Vec<String> {
+        if !self.resolved_hosts {
+            return Vec::new();
+        }
+
+        self.endpoints.iter()
+            .flat_map(|endpoint| ["--remote".to_string(), endpoint.address.to_string(), endpoint.port.to_string(), match endpoint.protocol {
+                Protocol::Udp => "udp".to_string(),
+                Protocol::Tcp => "tcp-client".to_string(),
+            }])
+            .collect()
+    }
+
+    pub async fn install(&self) -> Result<()> {
+        let ruleset = self.ruleset()?;
+        tokio::task::spawn_blocking(move || run("nft", &["-f", "-"], Some(&ruleset))).await?
+            .context("Failed to install the kill switch")?;
+        Ok(())
+    }
+
+    /// Removes the kill switch rules. Returns false if they were not installed.
+    pub async fn remove() -> Result<bool> {
+        tokio::task::spawn_blocking(|| {
+            if run("nft", &["list", "table", TABLE_FAMILY, TABLE_NAME], None).is_err() {
+                return Ok(false);
+            }
+            run("nft", &["delete", "table", TABLE_FAMILY, TABLE_NAME], None)
+                .context("Failed to remove the kill switch")?;
+            Ok(true)
+        }).await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(host: &str, port: u16, protocol: Protocol) -> Remote {
+        Remote { host: host.to_string(), port, protocol }
+    }
+
+    #[test]
+    fn parses_remotes_with_defaults() {
+        let directives = "client\nport 443\nproto tcp-client\nremote vpn.example.com\nremote 192.0.2.1 1194 udp\nremote 2001:db8::1 8443\n";
+
+        assert_eq!(KillSwitch::parse_remotes(directives), [
+            remote("vpn.example.com", 443, Protocol::Tcp),
+            remote("192.0.2.1", 1194, Protocol::Udp),
+            remote("2001:db8::1", 8443, Protocol::Tcp),
+        ]);
+    }
+
+    #[test]
+    fn applies_defaults_set_after_the_remotes() {
+        let directives = "remote 192.0.2.1\nremote 2001:db8::1 1195 udp6\nrport 1300\nproto tcp6\n";
+
+        assert_eq!(KillSwitch::parse_remotes(directives), [
+            remote("192.0.2.1", 1300, Protocol::Tcp),
+            remote("2001:db8::1", 1195, Protocol::Udp),
+        ]);
+    }
+
+    #[test]
+    fn parses_device() {
+        assert_eq!(KillSwitch::parse_device("client\n"), "tun*");
+        assert_eq!(KillSwitch::parse_device("dev tap\n"), "tap*");
+        assert_eq!(KillSwitch::parse_device("dev tun\n  dev vpn0  \n"), "vpn0");
+    }
+
+    #[test]
+    fn renders_ruleset() {
+        let kill_switch = KillSwitch {
+            endpoints: vec![
+                Endpoint { address: "192.0.2.1".parse().unwrap(), port: 1194, protocol: Protocol::Udp },
+                Endpoint { address: "2001:db8::1".parse().unwrap(), port: 443, protocol: Protocol::Tcp },
+            ],
+            device: "tun*".to_string(),
+            resolved_hosts: false,
+        };
+
+        assert_eq!(kill_switch.ruleset().unwrap(), "\
+table inet ovpnd_killswitch
+delete table inet ovpnd_killswitch
+table inet ovpnd_killswitch {
+\tchain output {
+\t\ttype filter hook output priority 0; policy drop;
+\t\toifname \"lo\" accept
+\t\toifname \"tun*\" accept
+\t\tudp sport 68 udp dport 67 accept
+\t\tudp sport 546 udp dport 547 accept
+\t\ticmpv6 type { nd-router-solicit, nd-neighbor-solicit, nd-neighbor-advert } accept
+\t\tip daddr 192.0.2.1 udp dport 1194 accept
+\t\tip6 daddr 2001:db8::1 tcp dport 443 accept
+\t}
+}
+");
+        assert!(kill_switch.to_arguments().is_empty());
+    }
+
+    #[test]
+    fn refuses_invalid_device_names() {
+        let mut kill_switch = KillSwitch { endpoints: Vec::new(), device: String::new(), resolved_hosts: false };
+        for device in ["tun0", "tap*", "ovpn-work.1", "abcdefghijklmno"] {
+            kill_switch.device = device.to_string();
+            assert!(kill_switch.ruleset().is_ok(), "{}", device);
+        }
+
+        for device in ["", "*", "..", "abcdefghijklmnop", "tun 0", "tun0\" accept", "tun/0", "tun:0", "tün0", "t*n0", "tun0\naccept"] {
+            kill_switch.device = device.to_string();
+            assert!(kill_switch.ruleset().is_err(), "{}", device);
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use std::io::Write;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+use anyhow::Result;
+use chrono::Utc;
+use log::kv::{Key, Value, VisitSource};
+use log::{Level, Log, Metadata, Record};
+use crate::daemon_config::LogBackendKind;
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+const SYSLOG_IDENTIFIER: &str = "ovpnd";
+
+static LOGGER: OnceLock<Logger> = OnceLock::new();
+
+/// Writes log records to stderr or natively to journald. Key-value pairs of a record (e.g.
+/// `info!(OVPN_CONFIG_NAME = name; "...")`) become journal fields and are omitted on stderr.
+pub struct Logger {
+    backend: RwLock<LogBackendKind>,
+    journal: Option<UnixDatagram>,
+}
+
+impl Logger {
+    /// Installs the logger with the stderr backend, until the daemon config is loaded.
+    pub fn init() -> Result<()> {
+        let logger = LOGGER.get_or_init(|| Logger {
+            backend: RwLock::new(LogBackendKind::Stderr),
+            journal: UnixDatagram::unbound().ok(),
+        });
+        log::set_logger(logger).map_err(|e| anyhow::anyhow!("Failed to install the logger: {}", e))?;
+        Ok(())
+    }
+
+    /// Switches the backend, keeping stderr if the journal is not available.
+    pub fn set_backend(backend: LogBackendKind) {
+        let Some(logger) = LOGGER.get() else { return };
+        let backend = match backend {
+            LogBackendKind::Journald if logger.journal.is_none() || !Path::new(JOURNAL_SOCKET).exists() => {
+                log::warn!("The journal is not available, logging to stderr instead");
+                LogBackendKind::Stderr
+            }
+            backend => backend,
+        };
+        *logger.backend.write().unwrap() = backend;
+    }
+
+    fn write_stderr(record: &Record) {
+        let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%S%.3fZ");
+        let _ = writeln!(std::io::stderr().lock(), "{} {:<5} [{}] {}", timestamp, record.level(), record.target(), record.args());
+    }
+
+    fn write_journal(journal: &UnixDatagram, record: &Record) -> std::io::Result<()> {
+        let priority = match record.level() {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug | Level::Trace => 7,
+        };
+
+        let mut payload = Vec::new();
+        Self::append_field(&mut payload, "MESSAGE", &record.args().to_string());
+        Self::append_field(&mut payload, "PRIORITY", &priority.to_string());
+        Self::append_field(&mut payload, "SYSLOG_IDENTIFIER", SYSLOG_IDENTIFIER);
+        Self::append_field(&mut payload, "TARGET", record.target());
+        if let (Some(file), Some(line)) = (record.file(), record.line()) {
+            Self::append_field(&mut payload, "CODE_FILE", file);
+            Self::append_field(&mut payload, "CODE_LINE", &line.to_string());
+        }
+
+        let mut fields = Fields(Vec::new());
+        let _ = record.key_values().visit(&mut fields);
+        for (name, value) in fields.0 {
+            Self::append_field(&mut payload, &name, &value);
+        }
+
+        journal.send_to(&payload, JOURNAL_SOCKET)?;
+        Ok(())
+    }
+
+    /// Encodes a field in the journal's native protocol, values with newlines are length-prefixed.
+    fn append_field(payload: &mut Vec<u8>, name: &str, value: &str) {
+        payload.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            payload.push(b'\n');
+            payload.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            payload.push(b'=');
+        }
+        payload.extend_from_slice(value.as_bytes());
+        payload.push(b'\n');
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let backend = *self.backend.read().unwrap();
+        match (backend, &self.journal) {
+            (LogBackendKind::Journald, Some(journal)) => if Self::write_journal(journal, record).is_err() {
+                Self::write_stderr(record);
+            },
+            _ => Self::write_stderr(record),
+        }
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Collects the key-value pairs of a record.
+struct Fields(Vec<(String, String)>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), log::kv::Error> {
+        self.0.push((key.as_str().to_string(), value.to_string()));
+        Ok(())
+    }
+}
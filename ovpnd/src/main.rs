@@ -1,7 +1,18 @@
 use std::sync::Arc;
 use anyhow::Result;
-use simple_logger::SimpleLogger;
+use log::{error, info, warn};
+use std::time::Duration;
+use tokio::time::{sleep, timeout};
+use crate::api::Api;
+use crate::daemon_config::ShutdownPolicy;
+use crate::dbus::Daemon;
+use crate::logger::Logger;
+use crate::metrics::Metrics;
+use crate::privileges::Privileges;
+use crate::session_manager::SessionManager;
 use crate::state::AppState;
+use crate::systemd::Systemd;
+use crate::websocket::WebSocket;
 
 mod state;
 mod config;
@@ -12,17 +23,139 @@ mod socket_manager;
 mod socket_server;
 mod response;
 mod command_handler;
+mod importer;
+mod bundle;
+mod network_manager;
+mod template;
+mod daemon_config;
+mod routes;
+mod openvpn_output;
+mod dns;
+mod system;
+mod kill_switch;
+mod hooks;
+mod runtime_state;
+mod management;
+mod systemd;
+mod privileges;
+mod logger;
+mod history;
+mod metrics;
+mod http;
+mod events;
+mod api;
+mod dbus;
+mod websocket;
 
 #[tokio::main]
 async fn main() -> Result<()> {
     // Setup logging
-    SimpleLogger::new().init()?;
+    Logger::init()?;
 
     // Create app state
     let app_state = AppState::new().await;
+    log::set_max_level(app_state.daemon_config.read().await.log_level);
+    Logger::set_backend(app_state.daemon_config.read().await.log_backend);
+
+    // Report store inconsistencies, repairing is left to `ovpn-cli config fsck --repair`
+    match app_state.config_manager.read().await.check().await {
+        Ok(issues) => for issue in issues {
+            warn!("Configuration store inconsistency: {:?}", issue);
+        },
+        Err(e) => error!("Failed to check the configuration store: {}", e),
+    }
+
+    for warning in Privileges::check(&*app_state.daemon_config.read().await) {
+        warn!("{}", warning);
+    }
+
+    // Pick up a session that was started before the daemon restarted
+    if let Err(e) = app_state.session_manager.reattach(Arc::clone(&app_state)).await {
+        error!("Failed to reattach to the previous session: {}", e);
+    }
 
     // Start the server
-    app_state.socket_server.start(Arc::clone(&app_state)).await?;
+    let socket_mode = app_state.daemon_config.read().await.socket_mode;
+    let listener = app_state.socket_server.bind(socket_mode).await?;
+    let metrics = app_state.daemon_config.read().await.metrics.clone();
+    tokio::spawn(Metrics::serve(metrics, Arc::clone(&app_state)));
+    let api = app_state.daemon_config.read().await.api.clone();
+    tokio::spawn(Api::serve(api, Arc::clone(&app_state)));
+    let dbus = app_state.daemon_config.read().await.dbus.clone();
+    tokio::spawn(Daemon::serve(dbus, Arc::clone(&app_state)));
+    let websocket = app_state.daemon_config.read().await.websocket.clone();
+    tokio::spawn(WebSocket::serve(websocket, Arc::clone(&app_state)));
+
+    let status = SessionManager::describe_status(app_state.active_session.read().await.as_ref()).await;
+    if let Err(e) = Systemd::notify(&format!("READY=1\nSTATUS={}", status)) {
+        error!("Failed to notify systemd: {}", e);
+    }
+    if let Some(interval) = Systemd::watchdog_interval() {
+        tokio::spawn(watchdog(Arc::clone(&app_state), interval));
+    }
+
+    // Apply the startup policy once the clients that are already waiting receive its events,
+    // later clients see the outcome in the session status
+    app_state.socket_server.accept_pending(&listener, Arc::clone(&app_state)).await;
+    tokio::spawn(SessionManager::autostart(Arc::clone(&app_state)));
+
+    app_state.socket_server.start(listener, Arc::clone(&app_state)).await?;
+
+    shutdown(app_state).await;
 
     return Ok(());
 }
+
+/// Keeps the systemd watchdog satisfied while the runtime and the session state are responsive.
+async fn watchdog(app_state: Arc<AppState>, interval: Duration) {
+    loop {
+        sleep(interval / 2).await;
+        match timeout(interval / 2, app_state.active_session.read()).await {
+            Ok(_) => if let Err(e) = Systemd::notify("WATCHDOG=1") {
+                error!("Failed to notify the systemd watchdog: {}", e);
+            },
+            Err(_) => warn!("The session state is locked, skipping the watchdog notification"),
+        }
+    }
+}
+
+/// Tells the clients that the daemon is going away, handles the active session according to
+/// the shutdown policy, and removes the socket.
+async fn shutdown(app_state: Arc<AppState>) {
+    let settings = app_state.daemon_config.read().await.shutdown.clone();
+    let has_active_session = app_state.has_active_session().await;
+
+    let message = match (has_active_session, settings.sessions) {
+        (true, ShutdownPolicy::Keep) => "The daemon is stopping, the active session keeps running",
+        (true, ShutdownPolicy::Stop) => "The daemon is stopping, the active session will be stopped",
+        (false, _) => "The daemon is stopping",
+    };
+    let config = app_state.active_session.read().await.as_ref().map(|session| session.config.clone());
+    if let Err(e) = app_state.socket_manager.lock().await.broadcast_daemon_event("stopping", message, config.as_ref()).await {
+        error!("Failed to broadcast the stopping event: {}", e);
+    }
+
+    if has_active_session && settings.sessions == ShutdownPolicy::Stop {
+        match timeout(settings.timeout, app_state.session_manager.stop(Arc::clone(&app_state))).await {
+            Ok(Ok(_)) => info!("The active session was stopped"),
+            Ok(Err(e)) => error!("Failed to stop the active session: {}", e),
+            Err(_) => {
+                error!("Stopping the active session took longer than {} seconds", settings.timeout.as_secs());
+                if let Err(e) = app_state.session_manager.kill(Arc::clone(&app_state)).await {
+                    error!("{}", e);
+                }
+            }
+        }
+    }
+
+    if let Err(e) = Systemd::notify("STOPPING=1") {
+        error!("Failed to notify systemd: {}", e);
+    }
+
+    if let Err(e) = app_state.socket_server.close().await {
+        error!("Failed to remove the socket: {}", e);
+    }
+
+    info!("Shutdown complete");
+    log::logger().flush();
+}
@@ -0,0 +1,70 @@
+use anyhow::{Context, Result};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+/// A connection to the management interface of an openvpn process. openvpn only serves one
+/// management client at a time, so the daemon keeps a single connection per session.
+pub struct ManagementClient {
+    reader: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl ManagementClient {
+    pub async fn connect(path: &str) -> Result<ManagementClient> {
+        let stream = UnixStream::connect(path).await
+            .with_context(|| format!("Failed to connect to the management socket '{}'", path))?;
+        let (reader, writer) = stream.into_split();
+
+        Ok(ManagementClient {
+            reader: BufReader::new(reader).lines(),
+            writer,
+        })
+    }
+
+    pub async fn send(&mut self, command: &str) -> Result<()> {
+        self.writer.write_all(format!("{}\n", command).as_bytes()).await?;
+        Ok(())
+    }
+
+    /// Returns the next line sent by openvpn, or `None` once the connection is closed.
+    pub async fn next_line(&mut self) -> Result<Option<String>> {
+        self.reader.next_line().await.map_err(Into::into)
+    }
+
+    /// Parses a real-time `>BYTECOUNT:received,sent` notification, enabled with `bytecount <seconds>`.
+    pub fn parse_bytecount(line: &str) -> Option<(u64, u64)> {
+        let (received, sent) = line.strip_prefix(">BYTECOUNT:")?.split_once(',')?;
+        Some((received.trim().parse().ok()?, sent.trim().parse().ok()?))
+    }
+
+    /// Extracts the message of a real-time `>LOG:time,flags,message` notification, enabled with `log on`.
+    pub fn parse_log(line: &str) -> Option<&str> {
+        let mut fields = line.strip_prefix(">LOG:")?.splitn(3, ',');
+        fields.next()?.parse::<u64>().ok()?;
+        fields.next()?;
+        fields.next()
+    }
+
+    /// Parses the `SUCCESS: verb=N` answer to the `verb` command without an argument.
+    pub fn parse_verb(line: &str) -> Option<u32> {
+        line.strip_prefix("SUCCESS: verb=")?.trim().parse().ok()
+    }
+
+    /// Extracts the state and its reason (e.g. `RECONNECTING` and `ping-restart`) from a real-time
+    /// `>STATE:` notification. The reason is empty for most states.
+    pub fn parse_state_change(line: &str) -> Option<(&str, &str)> {
+        let mut fields = line.strip_prefix(">STATE:")?.split(',');
+        fields.next()?.parse::<u64>().ok()?;
+        Some((fields.next()?, fields.next().unwrap_or("")))
+    }
+
+    /// Extracts the state name (e.g. `CONNECTED` or `RECONNECTING`) from a real-time
+    /// `>STATE:` notification or from a line of the `state` command output.
+    pub fn parse_state(line: &str) -> Option<&str> {
+        let fields = line.strip_prefix(">STATE:").unwrap_or(line);
+        let mut fields = fields.split(',');
+        fields.next()?.parse::<u64>().ok()?;
+        fields.next().filter(|state| !state.is_empty())
+    }
+}
@@ -0,0 +1,301 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::Utc;
+use log::{error, info};
+use common::command::{Commands, ConfigCommand, DaemonCommand, SessionCommand};
+use crate::daemon_config::MetricsSettings;
+use crate::http::{Connection, HttpListener, HttpRequest, HttpResponse};
+use crate::session::{SerializableSession, SessionStatus};
+use crate::state::AppState;
+
+const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Upper bounds of the command latency histogram, in seconds.
+const LATENCY_BUCKETS: [f64; 9] = [0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0];
+
+const STATES: [(SessionStatus, &str); 5] = [
+    (SessionStatus::Starting, "starting"),
+    (SessionStatus::Running, "running"),
+    (SessionStatus::Stopping, "stopping"),
+    (SessionStatus::Stopped, "stopped"),
+    (SessionStatus::Failed, "failed"),
+];
+
+#[derive(Default)]
+struct LatencyHistogram {
+    /// Number of observations in each of the `LATENCY_BUCKETS`, not cumulative.
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+#[derive(Default)]
+struct Counters {
+    /// The last status of every config that had a session, by config name.
+    states: BTreeMap<String, SessionStatus>,
+
+    /// Bytes received and sent by the sessions of each config.
+    bytes: BTreeMap<String, (u64, u64)>,
+
+    /// The last bytecount of the active session. openvpn counts from zero again when it restarts.
+    last_bytecount: Option<(u64, u64)>,
+
+    /// By config name and reason.
+    reconnects: BTreeMap<(String, String), u64>,
+    failures: BTreeMap<(String, String), u64>,
+
+    /// By command and whether it succeeded.
+    commands: BTreeMap<(String, bool), u64>,
+    latencies: BTreeMap<String, LatencyHistogram>,
+}
+
+/// Counters fed by the session state transitions and the socket commands, exposed in the
+/// OpenMetrics text format.
+pub struct Metrics {
+    counters: Mutex<Counters>,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Self {
+            counters: Mutex::new(Counters::default()),
+        }
+    }
+
+    /// Follows a status change of the active session.
+    pub fn record_status(&self, session: &SerializableSession) {
+        let mut counters = self.counters.lock().unwrap();
+        counters.states.insert(session.config.name.clone(), session.status.clone());
+
+        if matches!(session.status, SessionStatus::Stopped | SessionStatus::Failed) {
+            counters.last_bytecount = None;
+        }
+    }
+
+    /// Adds the traffic since the last bytecount of the active session to the totals of its
+    /// config. A bytecount lower than the last one means openvpn restarted and counts from zero.
+    pub fn record_traffic(&self, config: &str, received: u64, sent: u64) {
+        let mut counters = self.counters.lock().unwrap();
+        let (last_received, last_sent) = counters.last_bytecount.replace((received, sent)).unwrap_or_default();
+        let delta = |current: u64, last: u64| current.checked_sub(last).unwrap_or(current);
+
+        let bytes = counters.bytes.entry(config.to_string()).or_default();
+        bytes.0 += delta(received, last_received);
+        bytes.1 += delta(sent, last_sent);
+    }
+
+    pub fn record_reconnect(&self, config: &str, reason: &str) {
+        let reason = if reason.is_empty() { "unknown" } else { reason };
+        *self.counters.lock().unwrap().reconnects.entry((config.to_string(), reason.to_string())).or_default() += 1;
+    }
+
+    pub fn record_failure(&self, config: &str, reason: &str) {
+        *self.counters.lock().unwrap().failures.entry((config.to_string(), reason.to_string())).or_default() += 1;
+    }
+
+    pub fn record_command(&self, command: &str, success: bool, duration: Duration) {
+        let mut counters = self.counters.lock().unwrap();
+        *counters.commands.entry((command.to_string(), success)).or_default() += 1;
+
+        let seconds = duration.as_secs_f64();
+        let histogram = counters.latencies.entry(command.to_string()).or_default();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|bound| seconds <= *bound) {
+            histogram.buckets[bucket] += 1;
+        }
+        histogram.count += 1;
+        histogram.sum += seconds;
+    }
+
+    /// The command name used as a label: the subcommands without their options, e.g. `session start`.
+    pub fn command_name(commands: &Commands) -> &'static str {
+        match commands {
+            Commands::Config { commands } => match commands {
+                ConfigCommand::List => "config list",
+                ConfigCommand::Import { .. } => "config import",
+                ConfigCommand::Profile { .. } => "config profile",
+                ConfigCommand::Export { .. } => "config export",
+                ConfigCommand::Delete { .. } => "config delete",
+                ConfigCommand::Set { .. } => "config set",
+                ConfigCommand::Fsck { .. } => "config fsck",
+            },
+            Commands::Session { commands } => match commands {
+                SessionCommand::Start { .. } => "session start",
+                SessionCommand::Stop => "session stop",
+                SessionCommand::Status => "session status",
+                SessionCommand::History { .. } => "session history",
+            },
+            Commands::Daemon { commands } => match commands {
+                DaemonCommand::Reload => "daemon reload",
+            },
+        }
+    }
+
+    /// Renders every metric. `configs` are the names of all configs, so that the ones that never
+    /// had a session are reported as stopped.
+    pub fn render(&self, active_session: Option<&SerializableSession>, configs: &[String], socket_clients: usize) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut output = String::new();
+
+        let _ = writeln!(output, "# TYPE ovpnd_session_state gauge");
+        let _ = writeln!(output, "# HELP ovpnd_session_state Whether the session of a config is in the given state.");
+        let mut names = configs.to_vec();
+        names.extend(counters.states.keys().filter(|name| !configs.contains(name)).cloned());
+        for name in &names {
+            let status = match active_session {
+                Some(session) if &session.config.name == name => &session.status,
+                _ => counters.states.get(name).unwrap_or(&SessionStatus::Stopped),
+            };
+            for (state, label) in &STATES {
+                let _ = writeln!(output, "ovpnd_session_state{{config=\"{}\",state=\"{}\"}} {}", escape(name), label, (state == status) as u8);
+            }
+        }
+
+        let _ = writeln!(output, "# TYPE ovpnd_session_uptime_seconds gauge");
+        let _ = writeln!(output, "# HELP ovpnd_session_uptime_seconds Time since the active session was started.");
+        if let Some(session) = active_session {
+            let uptime = (Utc::now() - session.started).num_milliseconds() as f64 / 1000.0;
+            let _ = writeln!(output, "ovpnd_session_uptime_seconds{{config=\"{}\"}} {}", escape(&session.config.name), uptime);
+        }
+
+        for (metric, help, index) in [("ovpnd_received_bytes", "Bytes received through the tunnel.", 0), ("ovpnd_sent_bytes", "Bytes sent through the tunnel.", 1)] {
+            let _ = writeln!(output, "# TYPE {} counter", metric);
+            let _ = writeln!(output, "# HELP {} {}", metric, help);
+            for (name, (received, sent)) in &counters.bytes {
+                let _ = writeln!(output, "{}_total{{config=\"{}\"}} {}", metric, escape(name), if index == 0 { received } else { sent });
+            }
+        }
+
+        for (metric, help, values) in [
+            ("ovpnd_reconnects", "Reconnects of openvpn, by reason.", &counters.reconnects),
+            ("ovpnd_session_failures", "Sessions that ended without being stopped, by reason.", &counters.failures),
+        ] {
+            let _ = writeln!(output, "# TYPE {} counter", metric);
+            let _ = writeln!(output, "# HELP {} {}", metric, help);
+            for ((name, reason), count) in values {
+                let _ = writeln!(output, "{}_total{{config=\"{}\",reason=\"{}\"}} {}", metric, escape(name), escape(reason), count);
+            }
+        }
+
+        let _ = writeln!(output, "# TYPE ovpnd_socket_clients gauge");
+        let _ = writeln!(output, "# HELP ovpnd_socket_clients Clients connected to the control socket.");
+        let _ = writeln!(output, "ovpnd_socket_clients {}", socket_clients);
+
+        let _ = writeln!(output, "# TYPE ovpnd_commands counter");
+        let _ = writeln!(output, "# HELP ovpnd_commands Commands handled, from every interface, by result.");
+        for ((command, success), count) in &counters.commands {
+            let result = if *success { "ok" } else { "err" };
+            let _ = writeln!(output, "ovpnd_commands_total{{command=\"{}\",result=\"{}\"}} {}", escape(command), result, count);
+        }
+
+        let _ = writeln!(output, "# TYPE ovpnd_command_duration_seconds histogram");
+        let _ = writeln!(output, "# HELP ovpnd_command_duration_seconds Time taken to handle commands.");
+        for (command, histogram) in &counters.latencies {
+            let command = escape(command);
+            let mut cumulative = 0;
+            for (bound, count) in LATENCY_BUCKETS.iter().zip(histogram.buckets) {
+                cumulative += count;
+                let _ = writeln!(output, "ovpnd_command_duration_seconds_bucket{{command=\"{}\",le=\"{:?}\"}} {}", command, bound, cumulative);
+            }
+            let _ = writeln!(output, "ovpnd_command_duration_seconds_bucket{{command=\"{}\",le=\"+Inf\"}} {}", command, histogram.count);
+            let _ = writeln!(output, "ovpnd_command_duration_seconds_count{{command=\"{}\"}} {}", command, histogram.count);
+            let _ = writeln!(output, "ovpnd_command_duration_seconds_sum{{command=\"{}\"}} {}", command, histogram.sum);
+        }
+
+        let _ = writeln!(output, "# EOF");
+        output
+    }
+
+    /// Serves the metrics over HTTP on the configured address, until the daemon exits.
+    pub async fn serve(settings: MetricsSettings, app_state: Arc<AppState>) {
+        let Some(address) = &settings.listen else { return };
+        let listener = match HttpListener::bind(address, settings.mode).await {
+            Ok(listener) => listener,
+            Err(e) => return error!("Failed to start the metrics endpoint: {}", e),
+        };
+        info!("Metrics endpoint listening on {}", address);
+
+        loop {
+            match listener.accept().await {
+                Ok(stream) => { tokio::spawn(Self::handle_request(stream, Arc::clone(&app_state))); }
+                Err(e) => error!("Failed to accept a metrics connection: {}", e),
+            }
+        }
+    }
+
+    /// Answers a single HTTP request and closes the connection.
+    async fn handle_request(mut stream: Box<dyn Connection>, app_state: Arc<AppState>) {
+        let Ok(request) = HttpRequest::read(stream.as_mut(), 0).await else { return };
+
+        let response = match (request.method.as_str(), request.path.as_slice()) {
+            ("GET", [path]) if path == "metrics" => HttpResponse::new("200 OK", CONTENT_TYPE, Self::collect(&app_state).await),
+            ("GET", _) => HttpResponse::new("404 Not Found", "text/plain", "Not found, metrics are served on /metrics\n"),
+            _ => HttpResponse::new("405 Method Not Allowed", "text/plain", "Only GET is supported\n"),
+        };
+        if let Err(e) = response.send(stream.as_mut()).await {
+            error!("Failed to send the metrics: {}", e);
+        }
+    }
+
+    async fn collect(app_state: &AppState) -> String {
+        let active_session = match app_state.active_session.read().await.as_ref() {
+            Some(session) => Some(session.to_serializable().await),
+            None => None,
+        };
+        let configs = app_state.config_manager.read().await.get_index().get_entries().iter()
+            .map(|entry| entry.name.clone())
+            .collect::<Vec<_>>();
+        let socket_clients = app_state.socket_manager.lock().await.client_count().await;
+
+        app_state.metrics.render(active_session.as_ref(), &configs, socket_clients)
+    }
+}
+
+/// Escapes a label value, see the OpenMetrics specification.
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use common::command::Cli;
+    use super::*;
+
+    fn totals(metrics: &Metrics) -> Vec<String> {
+        metrics.render(None, &[], 0).lines()
+            .filter(|line| line.starts_with("ovpnd_received_bytes_total") || line.starts_with("ovpnd_sent_bytes_total"))
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn accumulates_traffic_across_openvpn_restarts() {
+        let metrics = Metrics::new();
+        metrics.record_traffic("x", 100, 10);
+        metrics.record_traffic("x", 300, 30);
+        // openvpn restarted and counts from zero again
+        metrics.record_traffic("x", 50, 5);
+
+        assert_eq!(totals(&metrics), [
+            "ovpnd_received_bytes_total{config=\"x\"} 350",
+            "ovpnd_sent_bytes_total{config=\"x\"} 35",
+        ]);
+    }
+
+    #[test]
+    fn counts_the_commands_of_every_interface() {
+        let metrics = Metrics::new();
+        let command = Cli::try_parse_from(["ovpn", "session", "start", "--name", "x"]).unwrap();
+        metrics.record_command(Metrics::command_name(&command.commands), true, Duration::from_millis(2));
+        metrics.record_command("invalid", false, Duration::from_secs(20));
+
+        let output = metrics.render(None, &[], 0);
+        assert!(output.contains("ovpnd_commands_total{command=\"session start\",result=\"ok\"} 1"));
+        assert!(output.contains("ovpnd_commands_total{command=\"invalid\",result=\"err\"} 1"));
+        assert!(output.contains("ovpnd_command_duration_seconds_bucket{command=\"session start\",le=\"0.005\"} 1"));
+        assert!(output.contains("ovpnd_command_duration_seconds_bucket{command=\"invalid\",le=\"10.0\"} 0"));
+        assert!(output.contains("ovpnd_command_duration_seconds_bucket{command=\"invalid\",le=\"+Inf\"} 1"));
+    }
+}
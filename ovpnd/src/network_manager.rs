@@ -0,0 +1,192 @@
+use anyhow::{Context, Result};
+use ini::Ini;
+
+const OPENVPN_SERVICE_TYPE: &str = "org.freedesktop.NetworkManager.openvpn";
+
+/// Keys of the `[vpn]` section that carry no configuration, e.g. NM-internal secret flags.
+const IGNORED_KEYS: [&str; 6] = ["service-type", "password-flags", "cert-pass-flags", "http-proxy-password-flags", "challenge-response-flags", "ta-dir"];
+
+/// An OpenVPN config translated from a NetworkManager keyfile.
+pub struct NmTranslation {
+    pub name: Option<String>,
+    pub config: String,
+    pub warnings: Vec<String>,
+}
+
+pub struct NetworkManager {}
+
+impl NetworkManager {
+    /// Translates a `.nmconnection` keyfile into an equivalent `.ovpn` config.
+    /// Returns `None` if the keyfile does not describe an OpenVPN connection.
+    pub fn translate(keyfile: &str) -> Result<Option<NmTranslation>> {
+        let ini = Ini::load_from_str_noescape(keyfile).context("The keyfile cannot be parsed")?;
+        let Some(vpn) = ini.section(Some("vpn")) else {
+            return Ok(None);
+        };
+        if vpn.get("service-type") != Some(OPENVPN_SERVICE_TYPE) {
+            return Ok(None);
+        }
+
+        let name = ini.section(Some("connection"))
+            .and_then(|c| c.get("id"))
+            .map(|id| id.split_whitespace().collect::<Vec<_>>().join("-"));
+
+        let mut lines = vec!["client".to_string(), "nobind".to_string()];
+        let mut warnings = Vec::new();
+
+        let default_port = vpn.get("port").unwrap_or("1194");
+        let default_proto = match (vpn.get("proto-tcp"), vpn.get("proto")) {
+            (Some("yes"), _) => "tcp",
+            (_, Some(proto)) => proto,
+            _ => "udp",
+        };
+        for remote in vpn.get("remote").unwrap_or_default().split([',', ' ']).filter(|r| !r.is_empty()) {
+            let mut parts = remote.split(':');
+            let host = parts.next().unwrap_or_default();
+            let port = parts.next().unwrap_or(default_port);
+            let proto = parts.next().unwrap_or(default_proto);
+            lines.push(format!("remote {} {} {}", host, port, proto));
+        }
+        if !lines.iter().any(|l| l.starts_with("remote ")) {
+            warnings.push("The connection has no remote, add one before starting it".to_string());
+        }
+
+        lines.push(format!("dev {}", vpn.get("dev-type").unwrap_or("tun")));
+
+        for (key, value) in vpn.iter() {
+            let line = match key {
+                _ if IGNORED_KEYS.contains(&key) => continue,
+                "remote" | "port" | "proto" | "proto-tcp" | "dev-type" => continue,
+                "connection-type" => match value {
+                    "tls" => continue,
+                    "password" | "password-tls" => {
+                        warnings.push("The connection uses password authentication; its credentials are not imported, configure auth-user-pass with a credentials file".to_string());
+                        "auth-user-pass".to_string()
+                    }
+                    "static-key" => continue,
+                    other => {
+                        warnings.push(format!("Unsupported connection-type '{}'", other));
+                        continue;
+                    }
+                },
+                "ca" | "cert" | "key" | "cipher" | "auth" | "tls-crypt" | "remote-cert-tls" | "tls-version-min" | "ping" | "ping-exit" | "ping-restart" | "connect-timeout" | "tun-mtu" | "fragment" => format!("{} {}", key, value),
+                "tunnel-mtu" => format!("tun-mtu {}", value),
+                "reneg-seconds" => format!("reneg-sec {}", value),
+                "data-ciphers" => format!("data-ciphers {}", value),
+                "mssfix" => match value {
+                    "yes" => "mssfix".to_string(),
+                    other => format!("mssfix {}", other),
+                },
+                "ta" => match vpn.get("ta-dir") {
+                    Some(direction) => format!("tls-auth {} {}", value, direction),
+                    None => format!("tls-auth {}", value),
+                },
+                "static-key" => match vpn.get("static-key-direction") {
+                    Some(direction) => format!("secret {} {}", value, direction),
+                    None => format!("secret {}", value),
+                },
+                "static-key-direction" => continue,
+                "comp-lzo" => match value {
+                    "no-by-default" | "no" => "comp-lzo no".to_string(),
+                    other => format!("comp-lzo {}", other),
+                },
+                "verify-x509-name" => match value.split_once(':') {
+                    Some((kind, name)) => format!("verify-x509-name {} {}", name, kind),
+                    None => format!("verify-x509-name {}", value),
+                },
+                "username" => {
+                    warnings.push(format!("The username '{}' is not imported, put it into an auth-user-pass credentials file", value));
+                    continue;
+                }
+                other => {
+                    warnings.push(format!("The setting '{}={}' cannot be mapped and was ignored", other, value));
+                    continue;
+                }
+            };
+            lines.push(line);
+        }
+
+        if ini.section(Some("vpn-secrets")).is_some() {
+            warnings.push("Stored secrets from [vpn-secrets] are not imported".to_string());
+        }
+
+        let mut config = lines.join("\n");
+        config.push('\n');
+
+        Ok(Some(NmTranslation { name, config, warnings }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEYFILE: &str = "[connection]
+id=My Company VPN
+type=vpn
+
+[vpn]
+service-type=org.freedesktop.NetworkManager.openvpn
+connection-type=tls
+remote=vpn.example.com:443:tcp, backup.example.com
+port=1195
+ca=/etc/ssl/company-ca.crt
+cert=/etc/ssl/me.crt
+key=/etc/ssl/me.key
+ta=/etc/ssl/ta.key
+ta-dir=1
+cipher=AES-256-GCM
+verify-x509-name=name:vpn.example.com
+comp-lzo=no-by-default
+password-flags=1
+
+[ipv4]
+method=auto
+";
+
+    #[test]
+    fn translates_an_openvpn_keyfile() {
+        let translation = NetworkManager::translate(KEYFILE).unwrap().unwrap();
+
+        assert_eq!(translation.name.as_deref(), Some("My-Company-VPN"));
+        assert_eq!(translation.config, "client\nnobind\n\
+            remote vpn.example.com 443 tcp\nremote backup.example.com 1195 udp\ndev tun\n\
+            ca /etc/ssl/company-ca.crt\ncert /etc/ssl/me.crt\nkey /etc/ssl/me.key\ntls-auth /etc/ssl/ta.key 1\n\
+            cipher AES-256-GCM\nverify-x509-name vpn.example.com name\ncomp-lzo no\n");
+        assert!(translation.warnings.is_empty());
+    }
+
+    #[test]
+    fn skips_other_connections() {
+        let wifi = "[connection]\nid=Home\ntype=wifi\n\n[wifi]\nssid=Home\n";
+        let wireguard = "[connection]\nid=wg\ntype=vpn\n\n[vpn]\nservice-type=org.freedesktop.NetworkManager.wireguard\n";
+
+        assert!(NetworkManager::translate(wifi).unwrap().is_none());
+        assert!(NetworkManager::translate(wireguard).unwrap().is_none());
+    }
+
+    #[test]
+    fn warns_about_settings_that_are_not_imported() {
+        let keyfile = "[vpn]
+service-type=org.freedesktop.NetworkManager.openvpn
+connection-type=password-tls
+username=alice
+proxy-type=socks
+
+[vpn-secrets]
+password=secret
+";
+
+        let translation = NetworkManager::translate(keyfile).unwrap().unwrap();
+
+        assert_eq!(translation.name, None);
+        assert_eq!(translation.config, "client\nnobind\ndev tun\nauth-user-pass\n");
+        assert_eq!(translation.warnings, [
+            "The connection has no remote, add one before starting it",
+            "The connection uses password authentication; its credentials are not imported, configure auth-user-pass with a credentials file",
+            "The username 'alice' is not imported, put it into an auth-user-pass credentials file",
+            "The setting 'proxy-type=socks' cannot be mapped and was ignored",
+            "Stored secrets from [vpn-secrets] are not imported",
+        ]);
+    }
+}
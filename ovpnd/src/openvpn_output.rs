@@ -0,0 +1,78 @@
+use serde::{Deserialize, Serialize};
+
+/// Connection details learned from the openvpn log while a session is running.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct SessionInfo {
+    pub tun_device: Option<String>,
+    pub local_ip: Option<String>,
+    pub remote_ip: Option<String>,
+    pub dns_servers: Vec<String>,
+    pub search_domains: Vec<String>,
+
+    /// Traffic of the tunnel so far, sampled through the management interface.
+    #[serde(default)]
+    pub bytes_received: u64,
+    #[serde(default)]
+    pub bytes_sent: u64,
+}
+
+/// Something of interest logged by openvpn.
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputEvent {
+    TunDeviceOpened(String),
+    PushReply(Vec<String>),
+}
+
+pub struct OpenVpnOutput {}
+
+impl OpenVpnOutput {
+    pub fn parse_line(line: &str) -> Option<OutputEvent> {
+        if let Some(rest) = line.split("TUN/TAP device ").nth(1) {
+            if let Some(device) = rest.strip_suffix(" opened").or_else(|| rest.split_whitespace().next()) {
+                return Some(OutputEvent::TunDeviceOpened(device.to_string()));
+            }
+        }
+
+        if let Some(rest) = line.split("PUSH_REPLY,").nth(1) {
+            let options = rest.trim_end_matches('\'')
+                .split(',')
+                .map(|o| o.trim().to_string())
+                .filter(|o| !o.is_empty())
+                .collect();
+            return Some(OutputEvent::PushReply(options));
+        }
+
+        None
+    }
+
+    /// Updates the session info with the options pushed by the server.
+    pub fn apply_push_reply(info: &mut SessionInfo, options: &[String]) {
+        for option in options {
+            let tokens = option.split_whitespace().collect::<Vec<_>>();
+            match tokens.as_slice() {
+                ["ifconfig", local, remote, ..] => {
+                    info.local_ip = Some(local.to_string());
+                    info.remote_ip = Some(remote.to_string());
+                }
+                ["dhcp-option", "DNS" | "DNS6", server, ..] | ["dns", "server", _, "address", server, ..] => {
+                    Self::push_unique(&mut info.dns_servers, server);
+                }
+                ["dhcp-option", "DOMAIN" | "DOMAIN-SEARCH", domain, ..] => {
+                    Self::push_unique(&mut info.search_domains, domain);
+                }
+                ["dns", "search-domains", domains @ ..] => {
+                    for domain in domains {
+                        Self::push_unique(&mut info.search_domains, domain);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn push_unique(values: &mut Vec<String>, value: &str) {
+        if !values.iter().any(|v| v == value) {
+            values.push(value.to_string());
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use std::fs;
+use std::path::Path;
+use anyhow::{anyhow, Context, Result};
+use nix::unistd::{geteuid, Group, User};
+use crate::daemon_config::{DaemonConfig, DbusBus, DnsBackendKind, PrivilegeSettings};
+use crate::system::run;
+
+/// Capability numbers, see capability(7).
+const CAP_SETGID: u32 = 6;
+const CAP_SETUID: u32 = 7;
+const CAP_NET_ADMIN: u32 = 12;
+
+/// The user besides root that org.ovpn.Daemon1.conf lets own the bus name.
+const DBUS_USER: &str = "ovpn";
+
+/// Reduces the privileges of openvpn, and checks that the daemon has the ones its features need.
+pub struct Privileges {}
+
+impl Privileges {
+    /// Arguments that make openvpn drop to the configured user and group after initialization,
+    /// and use the delegated tun device. These go after the config, so they take precedence.
+    pub fn to_arguments(settings: &PrivilegeSettings) -> Result<Vec<String>> {
+        let mut arguments = Vec::new();
+
+        if let Some(user) = &settings.openvpn_user {
+            User::from_name(user)?.ok_or_else(|| anyhow!("The openvpn user '{}' does not exist", user))?;
+            arguments.extend(["--user".to_string(), user.clone()]);
+        }
+        if let Some(group) = &settings.openvpn_group {
+            Group::from_name(group)?.ok_or_else(|| anyhow!("The openvpn group '{}' does not exist", group))?;
+            arguments.extend(["--group".to_string(), group.clone()]);
+        }
+        if settings.persist && (settings.openvpn_user.is_some() || settings.openvpn_group.is_some()) {
+            arguments.extend(["--persist-tun".to_string(), "--persist-key".to_string()]);
+        }
+
+        if let Some(device) = &settings.tun_device {
+            arguments.extend(["--dev".to_string(), device.clone(), "--dev-type".to_string(), "tun".to_string()]);
+        }
+
+        Ok(arguments)
+    }
+
+    /// Creates the persistent tun device unless it already exists, e.g. because the service
+    /// manager created it before starting the daemon. This needs CAP_NET_ADMIN.
+    pub async fn prepare_tun_device(device: &str) -> Result<()> {
+        if Path::new("/sys/class/net").join(device).exists() {
+            return Ok(());
+        }
+
+        let device = device.to_string();
+        tokio::task::spawn_blocking(move || run("ip", &["tuntap", "add", "dev", &device, "mode", "tun"], None)).await?
+            .context("Failed to create the tun device")?;
+        Ok(())
+    }
+
+    /// Describes the features that will not work with the privileges the daemon has.
+    pub fn check(config: &DaemonConfig) -> Vec<String> {
+        if geteuid().is_root() {
+            return Vec::new();
+        }
+
+        let capabilities = match Self::effective_capabilities() {
+            Ok(capabilities) => capabilities,
+            Err(e) => return vec![format!("Cannot check the capabilities of the daemon: {}", e)],
+        };
+        let has = |capability: u32| capabilities & (1 << capability) != 0;
+
+        let mut warnings = Vec::new();
+        if !has(CAP_NET_ADMIN) {
+            warnings.push("The daemon runs without CAP_NET_ADMIN, openvpn cannot configure the tunnel and the kill switch cannot be installed".to_string());
+        }
+        if config.privileges.tun_device.is_none() {
+            warnings.push("The daemon runs unprivileged without a tun_device, openvpn may fail to open /dev/net/tun".to_string());
+        }
+        if (config.privileges.openvpn_user.is_some() && !has(CAP_SETUID)) || (config.privileges.openvpn_group.is_some() && !has(CAP_SETGID)) {
+            warnings.push("openvpn_user and openvpn_group need CAP_SETUID and CAP_SETGID, openvpn will fail to drop privileges".to_string());
+        }
+        if config.dns_backend == DnsBackendKind::Resolvconf {
+            warnings.push("The resolvconf DNS backend needs write access to /etc/resolv.conf, which the daemon may lack".to_string());
+        }
+        let user = User::from_uid(geteuid()).ok().flatten().map(|user| user.name);
+        if config.dbus.enabled && config.dbus.bus == DbusBus::System && user.as_deref() != Some(DBUS_USER) {
+            warnings.push(format!("org.ovpn.Daemon1.conf only lets root and the {} user own the D-Bus name, add a policy for the user of the daemon", DBUS_USER));
+        }
+
+        warnings
+    }
+
+    /// Reads the effective capability set of the daemon from `/proc/self/status`.
+    fn effective_capabilities() -> Result<u64> {
+        let status = fs::read_to_string("/proc/self/status")?;
+        let value = status.lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .context("CapEff is missing")?;
+        Ok(u64::from_str_radix(value.trim(), 16)?)
+    }
+}
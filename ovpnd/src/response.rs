@@ -8,19 +8,27 @@ pub struct Response {
 
 impl Response {
     pub fn success(message: String) -> Response {
-        return Self { success: true, message };
+        Self { success: true, message }
     }
 
     pub fn fail(message: String) -> Response {
-        return Self { success: false, message };
+        Self { success: false, message }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
     }
 }
 
 impl std::fmt::Display for Response {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        return write!(f, "{}:{}", match self.success {
+        write!(f, "{}:{}", match self.success {
             true => "ok",
             false => "err"
-        }, self.message);
+        }, self.message)
     }
 }
\ No newline at end of file
@@ -0,0 +1,278 @@
+use std::net::{IpAddr, Ipv4Addr};
+use anyhow::{anyhow, Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use tokio::net::lookup_host;
+use common::command::RouteMode;
+
+/// Which traffic of a config is sent through the tunnel.
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RoutePolicy {
+    pub mode: RouteMode,
+
+    /// CIDRs, plain addresses or host names, resolved when the session starts.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub targets: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RouteVia {
+    Tunnel,
+    NetGateway,
+}
+
+/// A single route installed by openvpn because of the route policy.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(try_from = "StoredRoute")]
+pub struct EffectiveRoute {
+    pub network: String,
+    pub via: RouteVia,
+
+    #[serde(skip)]
+    address: IpAddr,
+
+    #[serde(skip)]
+    prefix: u8,
+}
+
+/// The serialized form of [`EffectiveRoute`], the address and prefix are parsed from the network.
+#[derive(Deserialize)]
+struct StoredRoute {
+    network: String,
+    via: RouteVia,
+}
+
+impl TryFrom<StoredRoute> for EffectiveRoute {
+    type Error = anyhow::Error;
+
+    fn try_from(route: StoredRoute) -> Result<Self> {
+        let (address, prefix) = RoutePolicy::parse_cidr(&route.network)
+            .with_context(|| format!("Invalid route network '{}'", route.network))?;
+        Ok(EffectiveRoute { network: route.network, via: route.via, address, prefix })
+    }
+}
+
+impl RoutePolicy {
+    /// Checks that every target is either a valid CIDR/address or looks like a host name.
+    pub fn validate(&self) -> Result<()> {
+        if self.mode != RouteMode::Full && self.targets.is_empty() {
+            return Err(anyhow!("The '{:?}' route mode needs at least one route", self.mode));
+        }
+
+        for target in &self.targets {
+            if Self::parse_cidr(target).is_none() && !Self::is_host_name(target) {
+                return Err(anyhow!("Invalid route '{}', expected a CIDR, an IP address or a host name", target));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the targets into the routes openvpn should install.
+    pub async fn resolve(&self) -> Result<Vec<EffectiveRoute>> {
+        let via = match self.mode {
+            RouteMode::Full => return Ok(Vec::new()),
+            RouteMode::Include => RouteVia::Tunnel,
+            RouteMode::Exclude => RouteVia::NetGateway,
+        };
+
+        let mut routes = Vec::new();
+        for target in &self.targets {
+            let (networks, resolved) = match Self::parse_cidr(target) {
+                Some(network) => (vec![network], false),
+                None => (lookup_host((target.as_str(), 0)).await
+                    .with_context(|| format!("Failed to resolve the route target '{}'", target))?
+                    .map(|address| (address.ip(), if address.is_ipv4() { 32 } else { 128 }))
+                    .collect(), true),
+            };
+            Self::add_routes(&mut routes, target, networks, resolved, &via)?;
+        }
+
+        Ok(routes)
+    }
+
+    /// Adds the networks of a target that are not in `routes` yet. `resolved` tells whether
+    /// they are the addresses of a host name.
+    fn add_routes(routes: &mut Vec<EffectiveRoute>, target: &str, networks: Vec<(IpAddr, u8)>, resolved: bool, via: &RouteVia) -> Result<()> {
+        for (address, prefix) in networks {
+            let route = EffectiveRoute { network: format!("{}/{}", address, prefix), via: via.clone(), address, prefix };
+            if route.address.is_ipv6() && *via == RouteVia::NetGateway {
+                // Host names commonly have AAAA records, which must not keep the session from starting
+                if resolved {
+                    warn!("Excluding IPv6 routes is not supported, skipping {} of '{}'", route.network, target);
+                    continue;
+                }
+                return Err(anyhow!("Excluding IPv6 routes ({}) is not supported", route.network));
+            }
+            if !routes.contains(&route) {
+                routes.push(route);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Translates resolved routes into openvpn arguments. In include mode the pushed default
+    /// gateway and routes are filtered out, but other pushed options (e.g. DNS) are kept. In
+    /// exclude mode the default gateway is redirected even if the server does not push it.
+    pub fn to_arguments(&self, routes: &[EffectiveRoute]) -> Vec<String> {
+        let mut arguments = Vec::new();
+        match self.mode {
+            RouteMode::Include => for filter in ["redirect-gateway", "redirect-private", "route ", "route-ipv6 "] {
+                arguments.extend(["--pull-filter".to_string(), "ignore".to_string(), filter.to_string()]);
+            },
+            RouteMode::Exclude => arguments.extend(["--redirect-gateway".to_string(), "def1".to_string()]),
+            RouteMode::Full => {}
+        }
+
+        for route in routes {
+            match route.address {
+                IpAddr::V4(address) => {
+                    arguments.extend(["--route".to_string(), address.to_string(), Self::prefix_to_mask(route.prefix).to_string()]);
+                    if route.via == RouteVia::NetGateway {
+                        arguments.push("net_gateway".to_string());
+                    }
+                }
+                IpAddr::V6(_) => arguments.extend(["--route-ipv6".to_string(), route.network.clone()]),
+            }
+        }
+
+        arguments
+    }
+
+    /// Parses `address[/prefix]`, clearing any host bits of the address.
+    fn parse_cidr(target: &str) -> Option<(IpAddr, u8)> {
+        let (address, prefix) = match target.split_once('/') {
+            Some((address, prefix)) => (address.parse::<IpAddr>().ok()?, Some(prefix.parse::<u8>().ok()?)),
+            None => (target.parse::<IpAddr>().ok()?, None),
+        };
+
+        match address {
+            IpAddr::V4(v4) => {
+                let prefix = prefix.unwrap_or(32);
+                (prefix <= 32).then(|| (IpAddr::V4(Ipv4Addr::from(u32::from(v4) & u32::from(Self::prefix_to_mask(prefix)))), prefix))
+            }
+            IpAddr::V6(v6) => {
+                let prefix = prefix.unwrap_or(128);
+                (prefix <= 128).then(|| {
+                    let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+                    (IpAddr::V6((u128::from(v6) & mask).into()), prefix)
+                })
+            }
+        }
+    }
+
+    fn prefix_to_mask(prefix: u8) -> Ipv4Addr {
+        Ipv4Addr::from(u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0))
+    }
+
+    fn is_host_name(target: &str) -> bool {
+        let labels = target.split('.').collect::<Vec<_>>();
+        target.len() <= 253
+            && labels.iter().all(|label| !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+            && !labels.last().is_some_and(|label| label.chars().all(|c| c.is_ascii_digit()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(mode: RouteMode, targets: &[&str]) -> RoutePolicy {
+        RoutePolicy { mode, targets: targets.iter().map(|target| target.to_string()).collect() }
+    }
+
+    fn networks(routes: &[EffectiveRoute]) -> Vec<&str> {
+        routes.iter().map(|route| route.network.as_str()).collect()
+    }
+
+    #[test]
+    fn validates_the_targets() {
+        assert!(policy(RouteMode::Include, &["10.0.0.0/8", "192.0.2.1", "2001:db8::/32", "intranet.example.com"]).validate().is_ok());
+        assert!(policy(RouteMode::Full, &[]).validate().is_ok());
+        assert!(policy(RouteMode::Exclude, &[]).validate().is_err());
+        assert!(policy(RouteMode::Include, &["10.0.0.0/33"]).validate().is_err());
+        assert!(policy(RouteMode::Include, &["192.0.2.300"]).validate().is_err());
+        assert!(policy(RouteMode::Include, &["bad_host.example.com"]).validate().is_err());
+    }
+
+    #[tokio::test]
+    async fn resolves_networks_without_host_bits() {
+        let routes = policy(RouteMode::Include, &["10.1.2.3/8", "192.0.2.1", "10.0.0.0/8", "2001:db8::1/32"]).resolve().await.unwrap();
+
+        assert_eq!(networks(&routes), ["10.0.0.0/8", "192.0.2.1/32", "2001:db8::/32"]);
+        assert!(routes.iter().all(|route| route.via == RouteVia::Tunnel));
+    }
+
+    #[tokio::test]
+    async fn resolves_host_names() {
+        let routes = policy(RouteMode::Include, &["localhost"]).resolve().await.unwrap();
+
+        assert!(networks(&routes).contains(&"127.0.0.1/32"));
+        assert!(policy(RouteMode::Include, &["does-not-exist.invalid"]).resolve().await.is_err());
+    }
+
+    #[test]
+    fn skips_resolved_ipv6_addresses_in_exclude_mode() {
+        let addresses = vec![("127.0.0.1".parse().unwrap(), 32), ("::1".parse().unwrap(), 128)];
+        let mut routes = Vec::new();
+
+        RoutePolicy::add_routes(&mut routes, "localhost", addresses.clone(), true, &RouteVia::NetGateway).unwrap();
+        assert_eq!(networks(&routes), ["127.0.0.1/32"]);
+
+        let mut routes = Vec::new();
+        RoutePolicy::add_routes(&mut routes, "localhost", addresses, true, &RouteVia::Tunnel).unwrap();
+        assert_eq!(networks(&routes), ["127.0.0.1/32", "::1/128"]);
+    }
+
+    #[tokio::test]
+    async fn excludes_resolved_host_names() {
+        let routes = policy(RouteMode::Exclude, &["localhost"]).resolve().await.unwrap();
+
+        assert_eq!(networks(&routes), ["127.0.0.1/32"]);
+        assert_eq!(routes[0].via, RouteVia::NetGateway);
+    }
+
+    #[tokio::test]
+    async fn rejects_ipv6_networks_in_exclude_mode() {
+        let error = policy(RouteMode::Exclude, &["2001:db8::/32"]).resolve().await.unwrap_err();
+
+        assert_eq!(error.to_string(), "Excluding IPv6 routes (2001:db8::/32) is not supported");
+    }
+
+    #[tokio::test]
+    async fn full_mode_adds_no_arguments() {
+        let policy = policy(RouteMode::Full, &[]);
+
+        assert!(policy.resolve().await.unwrap().is_empty());
+        assert!(policy.to_arguments(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn include_mode_filters_the_pushed_routes() {
+        let policy = policy(RouteMode::Include, &["10.0.0.0/8", "2001:db8::/32"]);
+        let routes = policy.resolve().await.unwrap();
+
+        assert_eq!(policy.to_arguments(&routes), [
+            "--pull-filter", "ignore", "redirect-gateway",
+            "--pull-filter", "ignore", "redirect-private",
+            "--pull-filter", "ignore", "route ",
+            "--pull-filter", "ignore", "route-ipv6 ",
+            "--route", "10.0.0.0", "255.0.0.0",
+            "--route-ipv6", "2001:db8::/32",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn exclude_mode_routes_around_the_tunnel() {
+        let policy = policy(RouteMode::Exclude, &["192.168.1.0/24", "198.51.100.7"]);
+        let routes = policy.resolve().await.unwrap();
+
+        assert_eq!(policy.to_arguments(&routes), [
+            "--redirect-gateway", "def1",
+            "--route", "192.168.1.0", "255.255.255.0", "net_gateway",
+            "--route", "198.51.100.7", "255.255.255.255", "net_gateway",
+        ]);
+    }
+}
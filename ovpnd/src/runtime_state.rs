@@ -0,0 +1,95 @@
+use std::fs::Permissions;
+use std::os::unix::fs::PermissionsExt;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use chrono::serde::ts_seconds;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+use common::paths::{OPENVPN_PATH, RUNTIME_PATH};
+use crate::config::ConfigEntry;
+use crate::openvpn_output::SessionInfo;
+use crate::routes::EffectiveRoute;
+use crate::session::SessionStatus;
+use crate::system::{process_command_line, process_start_time};
+
+const DIRECTORY_MODE: u32 = 0o700;
+const FILE_MODE: u32 = 0o600;
+
+/// What is needed to find the openvpn process of the active session again after the daemon
+/// restarts. openvpn runs in its own session (see `SessionManager::start`), so it outlives the daemon.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RuntimeState {
+    pub config: ConfigEntry,
+
+    #[serde(with = "ts_seconds")]
+    pub started: DateTime<Utc>,
+
+    pub status: SessionStatus,
+    pub pid: u32,
+
+    /// Start time of the process in clock ticks after boot, guards against pid reuse.
+    pub process_start_time: u64,
+
+    /// Path of the openvpn management socket.
+    pub management: String,
+
+    pub routes: Vec<EffectiveRoute>,
+    pub connection: SessionInfo,
+}
+
+impl RuntimeState {
+    /// Creates the runtime directory, which also holds the management sockets.
+    pub async fn prepare_directory() -> Result<()> {
+        fs::create_dir_all(RUNTIME_PATH).await?;
+        fs::set_permissions(RUNTIME_PATH, Permissions::from_mode(DIRECTORY_MODE)).await?;
+        Ok(())
+    }
+
+    pub fn get_management_path(guid: &str) -> String {
+        format!("{}/{}.mgmt", RUNTIME_PATH, guid)
+    }
+
+    fn get_path() -> String {
+        format!("{}/session.json", RUNTIME_PATH)
+    }
+
+    pub async fn load() -> Result<Option<RuntimeState>> {
+        let data = match fs::read_to_string(Self::get_path()).await {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).context("Failed to read the session runtime state"),
+        };
+
+        Ok(Some(serde_json::from_str(&data).context("Failed to parse the session runtime state")?))
+    }
+
+    pub async fn save(&self) -> Result<()> {
+        Self::prepare_directory().await?;
+        fs::write(Self::get_path(), serde_json::to_string_pretty(self)?).await?;
+        fs::set_permissions(Self::get_path(), Permissions::from_mode(FILE_MODE)).await?;
+        Ok(())
+    }
+
+    /// Removes the state file and the management socket.
+    pub async fn clear(&self) -> Result<()> {
+        for path in [Self::get_path(), self.management.clone()] {
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e).with_context(|| format!("Failed to remove '{}'", path)),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the pid still belongs to the openvpn process this state was written for.
+    pub fn is_same_process(&self) -> bool {
+        if process_start_time(self.pid) != Some(self.process_start_time) {
+            return false;
+        }
+
+        process_command_line(self.pid).is_some_and(|arguments| {
+            arguments.first().is_some_and(|program| program == OPENVPN_PATH) && arguments.contains(&self.management)
+        })
+    }
+}
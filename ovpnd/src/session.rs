@@ -1,35 +1,52 @@
 use std::sync::Arc;
 use chrono::{DateTime, Utc};
-use serde::{Serialize};
-use tokio::process::Child;
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 use crate::config::ConfigEntry;
+use crate::openvpn_output::SessionInfo;
+use crate::routes::EffectiveRoute;
+use crate::runtime_state::RuntimeState;
 use chrono::serde::ts_seconds;
 
-#[derive(Debug, Serialize, Clone, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum SessionStatus {
     Starting = 1 << 0,
     Running = 1 << 1,
     Stopping = 1 << 2,
     Stopped = 1 << 3,
-    // Crashed = (1 << 4) | (1 << 3),
+    /// openvpn exited without being stopped by the daemon.
+    Failed = (1 << 4) | (1 << 3),
 }
 
 pub struct Session {
     pub config: ConfigEntry,
     pub status: Arc<RwLock<SessionStatus>>,
     pub started: DateTime<Utc>,
-    pub process: Arc<RwLock<Child>>,
+    pub pid: u32,
+
+    /// Start time of the process in clock ticks after boot, see [`RuntimeState`].
+    pub process_start_time: u64,
+
+    /// Path of the openvpn management socket.
+    pub management: String,
+
+    pub routes: Vec<EffectiveRoute>,
+    pub info: Arc<RwLock<SessionInfo>>,
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct SerializableSession {
     pub config: ConfigEntry,
 
     #[serde(with = "ts_seconds")]
     pub started: DateTime<Utc>,
 
-    pub status: SessionStatus
+    pub status: SessionStatus,
+
+    /// Routes installed because of the route policy; empty in full-tunnel mode.
+    pub routes: Vec<EffectiveRoute>,
+
+    pub connection: SessionInfo,
 }
 
 impl Session {
@@ -37,8 +54,36 @@ impl Session {
         let status = self.status.read().await;
         SerializableSession {
             config: self.config.clone(),
-            started: self.started.clone(),
-            status: status.clone()
+            started: self.started,
+            status: status.clone(),
+            routes: self.routes.clone(),
+            connection: self.info.read().await.clone(),
+        }
+    }
+
+    pub async fn to_runtime_state(&self) -> RuntimeState {
+        RuntimeState {
+            config: self.config.clone(),
+            started: self.started,
+            status: self.status.read().await.clone(),
+            pid: self.pid,
+            process_start_time: self.process_start_time,
+            management: self.management.clone(),
+            routes: self.routes.clone(),
+            connection: self.info.read().await.clone(),
+        }
+    }
+
+    pub fn from_runtime_state(state: RuntimeState) -> Session {
+        Session {
+            config: state.config,
+            status: Arc::new(RwLock::new(state.status)),
+            started: state.started,
+            pid: state.pid,
+            process_start_time: state.process_start_time,
+            management: state.management,
+            routes: state.routes,
+            info: Arc::new(RwLock::new(state.connection)),
         }
     }
 }
\ No newline at end of file
@@ -1,167 +1,633 @@
 use std::process::Stdio;
 use std::sync::Arc;
 use std::time::Duration;
-use crate::config::{ConfigEntry, ConfigManager};
-use anyhow::{Context, Result};
-use chrono::{Utc};
-use log::{error, info};
-use nix::sys::{signal, wait};
+use crate::config::{ConfigEntry, ConfigKind, ConfigManager};
+use crate::daemon_config::StartupPolicy;
+use crate::events::{Event, TrafficEvent};
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::{debug, error, info, warn};
+use nix::sys::signal;
 use nix::unistd::Pid;
 use tokio::io::{AsyncBufReadExt, BufReader};
-use tokio::process::Command;
-use tokio::sync::RwLock;
-use tokio::time::timeout;
+use tokio::process::{Child, Command};
+use tokio::sync::{Mutex, RwLock};
+use tokio::time::{sleep, timeout};
 use common::paths::OPENVPN_PATH;
-use crate::session::{Session, SessionStatus};
+use crate::history::History;
+use crate::hooks::Hooks;
+use crate::kill_switch::KillSwitch;
+use crate::privileges::Privileges;
+use crate::management::ManagementClient;
+use crate::openvpn_output::{OpenVpnOutput, OutputEvent, SessionInfo};
+use crate::session::{SerializableSession, Session, SessionStatus};
+use crate::runtime_state::RuntimeState;
 use crate::session::SessionStatus::Stopping;
 use crate::state::AppState;
+use crate::system::{is_process_running, process_start_time};
+use crate::systemd::Systemd;
 
-pub struct SessionManager {}
+/// How often openvpn reports the traffic counters of a session, in seconds.
+const BYTECOUNT_INTERVAL: u64 = 5;
+
+/// How long openvpn may take to exit after SIGINT before it is killed.
+pub const STOP_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// openvpn only logs the options pushed by the server from this log level on.
+const PUSH_REPLY_VERB: u32 = 3;
+const MANAGEMENT_CONNECT_ATTEMPTS: u32 = 50;
+
+pub struct SessionManager {
+    /// Held while a session is being started, so two starts cannot both find no active session.
+    start_lock: Mutex<()>,
+}
 
 impl SessionManager {
-    pub fn new() -> SessionManager { Self {} }
+    pub fn new() -> SessionManager {
+        Self {
+            start_lock: Mutex::new(()),
+        }
+    }
 
     pub async fn start(&self, config: Box<ConfigEntry>, app_state: Arc<AppState>) -> Result<()> {
-        let config_path = ConfigManager::get_config_path(config.clone().as_ref());
+        let _start_guard = self.start_lock.lock().await;
+        if app_state.has_active_session().await {
+            return Err(anyhow!("Another session is already active"));
+        }
+
+        let config_path = ConfigManager::prepare_config_path(config.as_ref()).await?;
+
+        // The policy may have changed since the settings were made, so check them again
+        let daemon_config = app_state.daemon_config.read().await.clone();
+        config.validate(&daemon_config)?;
+
+        let mut kill_switch = match config.kill_switch {
+            true => Some(KillSwitch::for_config(config.as_ref()).await?),
+            false => None,
+        };
+        if let Some(device) = &daemon_config.privileges.tun_device {
+            Privileges::prepare_tun_device(device).await?;
+            if let Some(kill_switch) = &mut kill_switch {
+                kill_switch.device = device.clone();
+            }
+        }
+
+        Hooks::run(&SessionStatus::Starting, config.as_ref(), &SessionInfo::default(), daemon_config.hooks.timeout).await?;
+
         let mut command = Command::new(OPENVPN_PATH);
+        if let Some(kill_switch) = &kill_switch {
+            command.args(kill_switch.to_arguments());
+        }
         command.arg("--config");
         command.arg(config_path);
+        command.args(config.get_argument_tokens()?);
+        command.args(Privileges::to_arguments(&daemon_config.privileges)?);
+
+        // Lets the daemon follow the session again after it restarts, see SessionManager::reattach.
+        // openvpn waits for the daemon to connect, see SessionManager::watch_management
+        let management = RuntimeState::get_management_path(&config.guid);
+        RuntimeState::prepare_directory().await?;
+        command.args(["--management", management.as_str(), "unix", "--management-hold"]);
+        command.envs(&config.environment);
+
+        let routes = config.routes.resolve().await?;
+        command.args(config.routes.to_arguments(&routes));
 
         unsafe {
             command.pre_exec(|| {
-                nix::unistd::setsid().map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+                nix::unistd::setsid().map_err(std::io::Error::other)?;
                 Ok(())
             });
         }
 
-        let child_process = command
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .spawn()
-            .context("Failed to start the child process")?;
+        match &kill_switch {
+            Some(kill_switch) => {
+                kill_switch.install().await?;
+                info!("Kill switch installed for {}", kill_switch.device);
+            }
+            // Rules left behind by a session that exited on its own would block this one
+            None => if KillSwitch::remove().await? {
+                info!("Removed the kill switch of a previous session");
+            },
+        }
+
+        let child_process = match command.stdout(Stdio::piped()).stderr(Stdio::piped()).spawn() {
+            Ok(child_process) => child_process,
+            Err(e) => {
+                if kill_switch.is_some() {
+                    KillSwitch::remove().await?;
+                }
+                return Err(e).context("Failed to start the child process");
+            }
+        };
 
+        let process_id = child_process.id().context("Failed to get process id")?;
         let session = Session {
             status: Arc::new(RwLock::new(SessionStatus::Starting)),
             config: config.as_ref().clone(),
             started: Utc::now(),
-            process: Arc::new(RwLock::new(child_process)),
+            pid: process_id,
+            process_start_time: process_start_time(process_id).unwrap_or_default(),
+            management,
+            routes,
+            info: Arc::new(RwLock::new(SessionInfo::default())),
         };
 
-        let process_id = session.process.read().await.id().unwrap();
-
         info!("Child process has been started (PID: {})", process_id);
 
+        if let Err(e) = app_state.config_manager.write().await.update(&config.name, |entry| entry.last_used = Some(Utc::now())).await {
+            warn!("Failed to record the last use of the configuration: {}", e);
+        }
+
+        let started = session.started;
         *app_state.active_session.write().await = Some(session);
-        SessionManager::update_active_session(app_state.clone(), SessionStatus::Starting).await
-            .expect("Failed to change the status of the active session");
+        SessionManager::update_active_session(app_state.clone(), SessionStatus::Starting).await?;
 
-        self.start_monitoring_process(app_state.clone()).await?;
+        self.start_monitoring_process(child_process, config.as_ref(), started, app_state.clone()).await?;
 
         Ok(())
     }
 
-    pub async fn stop(&self, app_state: Arc<AppState>) -> Result<()> {
-        let active_session_guard = app_state.active_session.read().await;
-        let active_session = active_session_guard.as_ref().unwrap();
-        {
-            let mut child_process = active_session.process.write().await;
-            let pid = child_process.id().context("Failed to get process id")?;
-            let pid = Pid::from_raw(pid as i32);
+    /// Starts a session according to the startup policy, unless one was reattached. The outcome
+    /// is reported as an `autostart` daemon event.
+    pub async fn autostart(app_state: Arc<AppState>) {
+        if app_state.has_active_session().await {
+            return;
+        }
+
+        let startup = app_state.daemon_config.read().await.startup.clone();
+        let candidates: Vec<String> = {
+            let config_manager = app_state.config_manager.read().await;
+            let mut entries = config_manager.get_index().get_entries().iter()
+                .filter(|entry| entry.kind != ConfigKind::Template)
+                .collect::<Vec<_>>();
+            entries.sort_by_key(|entry| std::cmp::Reverse(entry.last_used));
+
+            match startup.policy {
+                StartupPolicy::None => return,
+                StartupPolicy::LastUsed => entries.iter().filter(|entry| entry.last_used.is_some()).take(1).map(|entry| entry.name.clone()).collect(),
+                StartupPolicy::Autostart => startup.configs.iter().cloned()
+                    .chain(entries.iter().filter(|entry| entry.autostart).map(|entry| entry.name.clone()))
+                    .collect::<Vec<_>>(),
+            }
+        };
 
-            SessionManager::update_active_session(app_state.clone(), SessionStatus::Stopping).await
-                .expect("Failed to change the status of the active session");
+        let mut outcome = "No configuration to start".to_string();
+        let mut last_config = None;
+        let mut tried = Vec::new();
+        for name in candidates {
+            if tried.contains(&name) {
+                continue;
+            }
+            tried.push(name.clone());
+
+            let config_entry = app_state.config_manager.read().await.get_by_name(name.clone()).await;
+            let result = match config_entry {
+                Ok(config_entry) => {
+                    last_config = Some(config_entry.clone());
+                    app_state.session_manager.start(config_entry, app_state.clone()).await
+                }
+                Err(e) => Err(e),
+            };
 
-            signal::kill(Pid::from_raw(-pid.as_raw()), signal::Signal::SIGINT)
-                .context("Failed to send SIGINT to process")?;
-            info!("Sent SIGINT to process (PID: {})", pid);
+            match result {
+                Ok(_) => {
+                    outcome = format!("Started session '{}'", name);
+                    break;
+                }
+                Err(e) => {
+                    outcome = format!("Failed to start session '{}': {}", name, e);
+                    warn!("{}", outcome);
+                }
+            }
+        }
+
+        info!("Startup policy {:?}: {}", startup.policy, outcome);
+        if let Err(e) = app_state.socket_manager.lock().await.broadcast_daemon_event("autostart", &outcome, last_config.as_deref()).await {
+            error!("Failed to broadcast the autostart event: {}", e);
+        }
+    }
 
-            match timeout(Duration::from_secs(5), child_process.wait()).await {
-                Ok(status) => info!("Process {} exited with status {:?}", pid, status),
+    /// Adopts the openvpn process of a session started before the daemon restarted, or cleans
+    /// up after it if the process is gone.
+    pub async fn reattach(&self, app_state: Arc<AppState>) -> Result<()> {
+        let Some(state) = RuntimeState::load().await? else { return Ok(()) };
+
+        if !state.is_same_process() {
+            info!("The openvpn process of session '{}' is gone, cleaning up", state.config.name);
+            state.clear().await?;
+            if let Some(link) = &state.connection.tun_device {
+                app_state.dns_manager.assume_applied(link).await;
+                app_state.dns_manager.revert().await?;
+            }
+            if state.config.kill_switch {
+                warn!("The kill switch stays installed until the session is stopped explicitly");
+            }
+            return Ok(());
+        }
+
+        info!("Reattached to the openvpn process of session '{}' (PID: {})", state.config.name, state.pid);
+        if let (SessionStatus::Running, Some(link)) = (&state.status, &state.connection.tun_device) {
+            app_state.dns_manager.assume_applied(link).await;
+        }
+
+        let (pid, started, management) = (state.pid, state.started, state.management.clone());
+        *app_state.active_session.write().await = Some(Session::from_runtime_state(state));
+
+        // Output of the process went to the previous daemon, so follow it through the management interface
+        tokio::spawn(SessionManager::watch_management(Arc::clone(&app_state), started, management, false));
+
+        // The process is not a child of this daemon, so it cannot be waited for
+        let app_state_clone = Arc::clone(&app_state);
+        tokio::spawn(async move {
+            while is_process_running(pid) {
+                sleep(Duration::from_secs(1)).await;
+            }
+            SessionManager::handle_exit(app_state_clone, started, "openvpn exited".to_string()).await;
+        });
+
+        Ok(())
+    }
+
+    /// Follows the management interface of a session: samples its traffic counters, reads the
+    /// tun device and the options pushed by the server from the log, and marks the session as
+    /// running once openvpn reports that it is connected. A `held` openvpn waits until the log
+    /// level is high enough for the pushed options to be logged.
+    async fn watch_management(app_state: Arc<AppState>, started: DateTime<Utc>, management: String, held: bool) {
+        // openvpn creates the socket during its initialization, shortly after it was started
+        let mut attempts = 0;
+        let mut client = loop {
+            match ManagementClient::connect(&management).await {
+                Ok(client) => break client,
+                Err(e) if attempts >= MANAGEMENT_CONNECT_ATTEMPTS && held => {
+                    // Nothing else would release the hold, so the session could never connect
+                    error!("{}, stopping the session", e);
+                    if let Some(session) = app_state.active_session.read().await.as_ref().filter(|s| s.started == started) {
+                        if let Err(e) = signal::kill(Pid::from_raw(-(session.pid as i32)), signal::Signal::SIGTERM) {
+                            error!("Failed to send SIGTERM to process (PID: {}): {}", session.pid, e);
+                        }
+                    }
+                    return;
+                }
+                Err(e) if attempts >= MANAGEMENT_CONNECT_ATTEMPTS => {
+                    warn!("{}, traffic and connection state changes will not be detected", e);
+                    return;
+                }
                 Err(_) => {
-                    error!("Process didn't exit within timeout, forcefully killing");
-                    child_process.kill().await.context("Failed to kill child process")?;
+                    attempts += 1;
+                    sleep(Duration::from_millis(200)).await;
                 }
             }
+        };
+
+        let commands = [format!("bytecount {}", BYTECOUNT_INTERVAL), "state on".to_string(), "log on".to_string(), "verb".to_string(), "state".to_string()];
+        for command in commands {
+            Self::send_management(&mut client, &command).await;
+        }
+
+        // The log level of the config, while it is raised to read the pushed options
+        let mut configured_verb = None;
+        while let Ok(Some(line)) = client.next_line().await {
+            let active_session_guard = app_state.active_session.read().await;
+            let Some(session) = active_session_guard.as_ref().filter(|s| s.started == started) else { break };
+
+            if let Some((received, sent)) = ManagementClient::parse_bytecount(&line) {
+                let mut info = session.info.write().await;
+                info.bytes_received = received;
+                info.bytes_sent = sent;
+                drop(info);
+                app_state.metrics.record_traffic(&session.config.name, received, sent);
+
+                let event = Event::Traffic(TrafficEvent {
+                    guid: session.config.guid.clone(),
+                    name: session.config.name.clone(),
+                    bytes_received: received,
+                    bytes_sent: sent,
+                });
+                drop(active_session_guard);
+                app_state.socket_manager.lock().await.publish(event);
+                continue;
+            }
+
+            if let Some(verb) = ManagementClient::parse_verb(&line) {
+                drop(active_session_guard);
+                if verb < PUSH_REPLY_VERB {
+                    configured_verb = Some(verb);
+                    Self::send_management(&mut client, &format!("verb {}", PUSH_REPLY_VERB)).await;
+                }
+                if held {
+                    Self::send_management(&mut client, "hold release").await;
+                }
+                continue;
+            }
+
+            if let Some(message) = ManagementClient::parse_log(&line) {
+                match OpenVpnOutput::parse_line(message) {
+                    Some(OutputEvent::TunDeviceOpened(device)) => session.info.write().await.tun_device = Some(device),
+                    Some(OutputEvent::PushReply(options)) => {
+                        OpenVpnOutput::apply_push_reply(&mut *session.info.write().await, &options);
+                        drop(active_session_guard);
+                        if let Some(verb) = configured_verb {
+                            Self::send_management(&mut client, &format!("verb {}", verb)).await;
+                        }
+                    }
+                    None => {}
+                }
+                continue;
+            }
+
+            if let Some(("RECONNECTING", reason)) = ManagementClient::parse_state_change(&line) {
+                app_state.metrics.record_reconnect(&session.config.name, reason);
+                // The server pushes the options again after reconnecting, and they may have changed
+                let mut info = session.info.write().await;
+                info.dns_servers.clear();
+                info.search_domains.clear();
+                drop(info);
+                let is_running = *session.status.read().await == SessionStatus::Running;
+                drop(active_session_guard);
+                if configured_verb.is_some() {
+                    Self::send_management(&mut client, &format!("verb {}", PUSH_REPLY_VERB)).await;
+                }
+                if is_running {
+                    Self::update_status(&app_state, SessionStatus::Starting).await;
+                }
+                continue;
+            }
+            if ManagementClient::parse_state(&line) != Some("CONNECTED") {
+                continue;
+            }
+            let status = session.status.read().await.clone();
+            let connection = session.info.read().await.clone();
+            drop(active_session_guard);
+            match status {
+                SessionStatus::Starting => Self::update_status(&app_state, SessionStatus::Running).await,
+                // e.g. after reattaching, the settings applied by the previous daemon are not known
+                SessionStatus::Running => Self::apply_dns(&app_state, &connection).await,
+                _ => {}
+            }
+        }
+    }
+
+    /// Changes the status of the active session from the management watcher, which must not
+    /// panic when the session was stopped in the meantime.
+    async fn update_status(app_state: &Arc<AppState>, status: SessionStatus) {
+        if let Err(e) = SessionManager::update_active_session(Arc::clone(app_state), status).await {
+            error!("Failed to change the status of the active session: {}", e);
+        }
+    }
+
+    async fn send_management(client: &mut ManagementClient, command: &str) {
+        if let Err(e) = client.send(command).await {
+            error!("Failed to send '{}' to the openvpn management interface: {}", command, e);
+        }
+    }
+
+    pub async fn stop(&self, app_state: Arc<AppState>) -> Result<()> {
+        let active_session_guard = app_state.active_session.read().await;
+        // Another stop, or the exit of openvpn, may have ended the session in the meantime
+        let Some(active_session) = active_session_guard.as_ref() else { return Ok(()) };
+        let process_id = active_session.pid;
+        let pid = Pid::from_raw(process_id as i32);
+        drop(active_session_guard);
+
+        SessionManager::update_active_session(app_state.clone(), SessionStatus::Stopping).await?;
+
+        signal::kill(Pid::from_raw(-pid.as_raw()), signal::Signal::SIGINT)
+            .context("Failed to send SIGINT to process")?;
+        info!("Sent SIGINT to process (PID: {})", pid);
+
+        // The process may not be a child of this daemon after a restart, so poll instead of waiting
+        let exited = async {
+            while is_process_running(process_id) {
+                sleep(Duration::from_millis(100)).await;
+            }
+        };
+        let exit_reason = match timeout(STOP_TIMEOUT, exited).await {
+            Ok(_) => {
+                info!("Process {} exited", pid);
+                "Stopped".to_string()
+            }
+            Err(_) => {
+                error!("Process didn't exit within timeout, forcefully killing");
+                signal::kill(Pid::from_raw(-pid.as_raw()), signal::Signal::SIGKILL)
+                    .context("Failed to kill child process")?;
+                format!("Killed after not stopping within {} seconds", STOP_TIMEOUT.as_secs())
+            }
+        };
+
+        SessionManager::finish_stop(app_state, &exit_reason).await
+    }
+
+    /// Kills the openvpn process group of the active session without waiting for it, and cleans
+    /// up like [`SessionManager::stop`]. Used when stopping it properly takes too long.
+    pub async fn kill(&self, app_state: Arc<AppState>) -> Result<()> {
+        let active_session_guard = app_state.active_session.read().await;
+        let Some(active_session) = active_session_guard.as_ref() else { return Ok(()) };
+
+        // The process may have exited in the meantime, and its id been reused by another group
+        let pid = active_session.pid;
+        if active_session.to_runtime_state().await.is_same_process() {
+            signal::kill(Pid::from_raw(-(pid as i32)), signal::Signal::SIGKILL)
+                .context("Failed to kill child process")?;
+            error!("Killed the openvpn process (PID: {})", pid);
         }
         drop(active_session_guard);
 
-        SessionManager::update_active_session(app_state.clone(), SessionStatus::Stopped).await
-            .expect("Failed to change the status of the active session");
+        SessionManager::finish_stop(app_state, "Killed after not stopping in time").await
+    }
+
+    /// Completes stopping the active session: records it in the history and marks it as stopped,
+    /// unless that already happened, then removes the kill switch and forgets the session.
+    async fn finish_stop(app_state: Arc<AppState>, exit_reason: &str) -> Result<()> {
+        let active_session_guard = app_state.active_session.read().await;
+        let Some(active_session) = active_session_guard.as_ref() else { return Ok(()) };
+        let kill_switch = active_session.config.kill_switch;
+        let is_stopped = *active_session.status.read().await == SessionStatus::Stopped;
+        if !is_stopped {
+            SessionManager::record_history(&app_state, active_session, SessionStatus::Stopped, exit_reason).await;
+        }
+        drop(active_session_guard);
+
+        // Reverts the DNS settings and runs the hooks, see SessionManager::on_status_changed
+        if !is_stopped {
+            if let Err(e) = SessionManager::update_active_session(app_state.clone(), SessionStatus::Stopped).await {
+                error!("Failed to change the status of the active session: {}", e);
+            }
+        }
+
+        if kill_switch && KillSwitch::remove().await? {
+            info!("Kill switch removed");
+        }
 
         *app_state.active_session.write().await = None;
 
         Ok(())
     }
 
-    async fn start_monitoring_process(&self, app_state: Arc<AppState>) -> Result<()> {
-        let active_session_guard = app_state.active_session.read().await;
-        let active_session = active_session_guard.as_ref().unwrap();
-        let mut process = active_session.process.write().await;
-        let pid = process.id().context("Failed to get process id")?;
-
+    async fn start_monitoring_process(&self, mut process: Child, config: &ConfigEntry, started: DateTime<Utc>, app_state: Arc<AppState>) -> Result<()> {
         let stdout = process.stdout.take()
             .context("Failed to get stdout from child process")?;
         let mut reader = BufReader::new(stdout).lines();
-        let app_state_clone = Arc::clone(&app_state);
+        let (guid, name) = (config.guid.clone(), config.name.clone());
         tokio::spawn(async move {
             while let Some(line) = reader.next_line().await.unwrap_or(None) {
-                println!("OUT >> {}", line);
-
-                if line.contains("Initialization Sequence Completed") {
-                    SessionManager::update_active_session(app_state_clone.clone(), SessionStatus::Running).await
-                        .expect("Failed to change the status of the active session");
-                }
+                info!(target: "openvpn", OVPN_SESSION_GUID = guid.as_str(), OVPN_CONFIG_NAME = name.as_str(), OVPN_STREAM = "stdout"; "{}", line);
             }
         });
 
         let stderr = process.stderr.take()
             .context("Failed to get stderr from child process")?;
         let mut reader = BufReader::new(stderr).lines();
+        let (guid, name) = (config.guid.clone(), config.name.clone());
         tokio::spawn(async move {
             while let Some(line) = reader.next_line().await.unwrap_or(None) {
-                println!("ERR >> {}", line);
+                warn!(target: "openvpn", OVPN_SESSION_GUID = guid.as_str(), OVPN_CONFIG_NAME = name.as_str(), OVPN_STREAM = "stderr"; "{}", line);
             }
         });
 
+        let management = RuntimeState::get_management_path(&config.guid);
+        tokio::spawn(SessionManager::watch_management(Arc::clone(&app_state), started, management, true));
+
         let app_state_clone = Arc::clone(&app_state);
         tokio::spawn(async move {
-            let pid = Pid::from_raw(pid as i32);
-            match wait::waitpid(pid, None) {
-                Ok(_) => {
-                    let app_state_clone = app_state_clone.clone();
-                    let active_session_guard = app_state_clone.active_session.read().await;
-                    let active_session = active_session_guard.as_ref().unwrap();
-                    let status = active_session.status.read().await;
-                    if status.clone() == Stopping {
-                        println!("The process was stopped by the daemon, skipping exit handling");
-                        return;
-                    }
-                    drop(status);
-                    drop(active_session_guard);
-
-                    SessionManager::update_active_session(app_state_clone.clone(), SessionStatus::Stopped).await
-                        .expect("Failed to change the status of the active session");
-                    *app_state_clone.active_session.write().await = None;
-                    println!(">>> Process has exited")
-                }
-                Err(_) => error!("Failed to wait for process"),
+            match process.wait().await {
+                Ok(status) => SessionManager::handle_exit(app_state_clone, started, format!("openvpn exited with {}", status)).await,
+                Err(e) => error!("Failed to wait for process: {}", e),
             }
         });
 
         Ok(())
     }
 
+    /// Marks the session as failed if its process exited without being stopped by the daemon.
+    async fn handle_exit(app_state: Arc<AppState>, started: DateTime<Utc>, exit_reason: String) {
+        let active_session_guard = app_state.active_session.read().await;
+        // The daemon may have stopped the session, and started a new one, in the meantime
+        let Some(active_session) = active_session_guard.as_ref().filter(|s| s.started == started) else {
+            debug!("The process was stopped by the daemon, skipping exit handling");
+            return;
+        };
+        let status = active_session.status.read().await;
+        if matches!(*status, Stopping | SessionStatus::Stopped) {
+            debug!("The process was stopped by the daemon, skipping exit handling");
+            return;
+        }
+        let kill_switch = active_session.config.kill_switch;
+        warn!(OVPN_SESSION_GUID = active_session.config.guid.as_str(), OVPN_CONFIG_NAME = active_session.config.name.as_str();
+            "The openvpn process of session '{}' has exited: {}", active_session.config.name, exit_reason);
+        drop(status);
+        SessionManager::record_history(&app_state, active_session, SessionStatus::Failed, &exit_reason).await;
+        app_state.metrics.record_failure(&active_session.config.name, &exit_reason);
+        drop(active_session_guard);
+        if kill_switch {
+            warn!("The kill switch stays installed until the session is stopped explicitly");
+        }
+
+        if let Err(e) = SessionManager::update_active_session(app_state.clone(), SessionStatus::Failed).await {
+            error!("Failed to change the status of the active session: {}", e);
+        }
+        *app_state.active_session.write().await = None;
+    }
+
+    /// Changes the status of the active session, then broadcasts the change and applies its side
+    /// effects without holding the session, as the hooks may take a while.
     async fn update_active_session(app_state: Arc<AppState>, status: SessionStatus) -> Result<()> {
-        let app_state_clone = Arc::clone(&app_state);
-        let active_session = app_state_clone.active_session.read().await;
-        *(*active_session).as_ref().unwrap().status.write().await = status;
+        let active_session_guard = app_state.active_session.read().await;
+        let active_session = active_session_guard.as_ref().context("The session is no longer active")?;
+        *active_session.status.write().await = status;
 
-        let app_state_clone = Arc::clone(&app_state);
-        let active_session = (*active_session).as_ref().unwrap().to_serializable().await;
-        let mut socket_manager = app_state_clone.socket_manager.lock().await;
-        socket_manager.broadcast_status_change(&active_session).await
-            .expect("Failed to broadcast status change");
+        let runtime_state = active_session.to_runtime_state().await;
+        let persisted = match runtime_state.status {
+            SessionStatus::Stopped | SessionStatus::Failed => runtime_state.clear().await,
+            _ => runtime_state.save().await,
+        };
+        if let Err(e) = persisted {
+            error!("Failed to update the session runtime state: {}", e);
+        }
+
+        let active_session = active_session.to_serializable().await;
+        drop(active_session_guard);
+
+        info!(OVPN_SESSION_GUID = active_session.config.guid.as_str(), OVPN_CONFIG_NAME = active_session.config.name.as_str();
+            "Session '{}' is {:?}", active_session.config.name, active_session.status);
+        app_state.metrics.record_status(&active_session);
+        if let Err(e) = app_state.socket_manager.lock().await.broadcast_status_change(&active_session).await {
+            error!("Failed to broadcast the status change: {}", e);
+        }
+
+        SessionManager::on_status_changed(app_state, &active_session).await;
 
         Ok(())
     }
+
+    /// Adds a session that ended to the history, failures are only logged.
+    async fn record_history(app_state: &AppState, session: &Session, final_state: SessionStatus, exit_reason: &str) {
+        let record = History::describe(session, final_state, exit_reason).await;
+        let settings = app_state.daemon_config.read().await.history.clone();
+        if let Err(e) = History::record(&settings, record).await {
+            error!("Failed to record the session in the history: {}", e);
+        }
+    }
+
+    /// A one-line summary of the active session, shown by `systemctl status`.
+    pub async fn describe_status(session: Option<&Session>) -> String {
+        match session {
+            Some(session) => format!("Session '{}' is {:?}", session.config.name, session.status.read().await),
+            None => "No active session".to_string(),
+        }
+    }
+
+    /// Applies the DNS settings pushed by the server to the tun device of the session.
+    async fn apply_dns(app_state: &AppState, connection: &SessionInfo) {
+        match &connection.tun_device {
+            Some(link) => {
+                if let Err(e) = app_state.dns_manager.apply(link, &connection.dns_servers, &connection.search_domains).await {
+                    error!("Failed to apply the pushed DNS settings: {}", e);
+                }
+            }
+            None => warn!("The tun device of the session is unknown, pushed DNS settings are not applied"),
+        }
+    }
+
+    /// Side effects of status changes, applied after the change has been broadcast.
+    async fn on_status_changed(app_state: Arc<AppState>, session: &SerializableSession) {
+        let status = match session.status {
+            SessionStatus::Stopped => "No active session".to_string(),
+            SessionStatus::Failed => format!("No active session, '{}' failed", session.config.name),
+            _ => format!("Session '{}' is {:?}", session.config.name, session.status),
+        };
+        if let Err(e) = Systemd::notify(&format!("STATUS={}", status)) {
+            error!("Failed to notify systemd: {}", e);
+        }
+
+        match session.status {
+            SessionStatus::Running => SessionManager::apply_dns(&app_state, &session.connection).await,
+            SessionStatus::Stopped | SessionStatus::Failed => {
+                if let Err(e) = app_state.dns_manager.revert().await {
+                    error!("Failed to revert the DNS settings: {}", e);
+                }
+                if let Err(e) = ConfigManager::remove_rendered_config(&session.config).await {
+                    error!("Failed to remove the rendered configuration: {}", e);
+                }
+            }
+            _ => {}
+        }
+
+        // Starting hooks run before openvpn is spawned, see SessionManager::start. Running hooks
+        // must not hold up reading the openvpn output, the others finish before the session is gone,
+        // so they also complete when the daemon shuts down.
+        if matches!(session.status, SessionStatus::Running | SessionStatus::Stopped | SessionStatus::Failed) {
+            let (status, config, info) = (session.status.clone(), session.config.clone(), session.connection.clone());
+            let time_limit = app_state.daemon_config.read().await.hooks.timeout;
+            let hooks = async move {
+                if let Err(e) = Hooks::run(&status, &config, &info, time_limit).await {
+                    error!("Failed to run the {:?} hooks: {}", status, e);
+                }
+            };
+            match session.status {
+                SessionStatus::Running => _ = tokio::spawn(hooks),
+                _ => hooks.await,
+            }
+        }
+    }
 }
\ No newline at end of file
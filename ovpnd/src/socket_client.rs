@@ -20,4 +20,11 @@ impl SocketClient {
         let message = format!("!{}:{}\n", message.len(), message);
         self.writer.write_all(message.as_bytes()).await.map_err(anyhow::Error::from)
     }
+
+    /// Sends an event that is not about a session, in place of the guid it starts with `daemon`.
+    pub async fn send_daemon_event(&mut self, kind: &str, message: &str) -> anyhow::Result<()> {
+        let message = format!("daemon:{}:{}", kind, message.replace('\n', " "));
+        let message = format!("!{}:{}\n", message.len(), message);
+        self.writer.write_all(message.as_bytes()).await.map_err(anyhow::Error::from)
+    }
 }
\ No newline at end of file
@@ -1,14 +1,21 @@
 use std::collections::HashMap;
 use std::sync::Arc;
 use tokio::net::unix::OwnedWriteHalf;
-use tokio::sync::{Mutex, RwLock};
+use tokio::sync::{broadcast, Mutex, RwLock};
 use anyhow::Result;
+use log::{error, info};
+use crate::config::ConfigEntry;
+use crate::events::{DaemonEvent, Event};
 use crate::session::SerializableSession;
 use crate::socket_client::SocketClient;
 
+/// Events that subscribers have not received yet when more arrive are dropped for them.
+const EVENT_CAPACITY: usize = 64;
+
 pub struct SocketManager {
     active_clients: RwLock<HashMap<u64, Arc<Mutex<SocketClient>>>>,
     last_client_id: u64,
+    events: broadcast::Sender<Event>,
 }
 
 impl SocketManager {
@@ -16,6 +23,7 @@ impl SocketManager {
         Self {
             active_clients: RwLock::new(HashMap::new()),
             last_client_id: 0,
+            events: broadcast::channel(EVENT_CAPACITY).0,
         }
     }
 
@@ -30,7 +38,22 @@ impl SocketManager {
         active_clients.insert(client_id, socket_client.clone());
         self.last_client_id = client_id;
 
-        return socket_client;
+        socket_client
+    }
+
+    /// Receives the events broadcast to the socket clients from now on, and the ones that are
+    /// only published to subscribers.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Sends an event to the subscribers only, for events the socket protocol has no format for.
+    pub fn publish(&self, event: Event) {
+        _ = self.events.send(event);
+    }
+
+    pub async fn client_count(&self) -> usize {
+        self.active_clients.read().await.len()
     }
 
     pub async fn remove_client(&mut self, client_id: u64) {
@@ -42,9 +65,29 @@ impl SocketManager {
         let active_clients = self.active_clients.read().await;
         for client in active_clients.values() {
             let mut client = client.lock().await;
-            client.send_status_update(session).await
-                .expect("Failed to send status update");
+            // A client that went away must not keep the others from being notified
+            if let Err(e) = client.send_status_update(session).await {
+                error!("Failed to send the status update to a client: {}", e);
+            }
+        }
+        _ = self.events.send(Event::Status(Box::new(session.clone())));
+
+        Ok(())
+    }
+
+    /// Sends a daemon event to the clients. `config` is the config or session the event is
+    /// about, if any, and is attached to the journal entry.
+    pub async fn broadcast_daemon_event(&mut self, kind: &str, message: &str, config: Option<&ConfigEntry>) -> Result<()> {
+        match config {
+            Some(config) => info!(OVPN_EVENT = kind, OVPN_SESSION_GUID = config.guid.as_str(), OVPN_CONFIG_NAME = config.name.as_str(); "Daemon event {}: {}", kind, message),
+            None => info!(OVPN_EVENT = kind; "Daemon event {}: {}", kind, message),
+        }
+        let active_clients = self.active_clients.read().await;
+        for client in active_clients.values() {
+            let mut client = client.lock().await;
+            client.send_daemon_event(kind, message).await?;
         }
+        _ = self.events.send(Event::Daemon(DaemonEvent { kind: kind.to_string(), message: message.to_string() }));
 
         Ok(())
     }
@@ -1,34 +1,88 @@
 use std::fs::Permissions;
 use anyhow::Result;
 use std::sync::Arc;
+use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
 use clap::{Parser};
+use futures::FutureExt;
 use log::{error, info};
 use tokio::fs;
 use tokio::io::{AsyncBufReadExt, BufReader};
 use tokio::net::{UnixListener, UnixStream};
+use tokio::net::unix::OwnedReadHalf;
+use tokio::sync::Mutex;
+use tokio::signal::unix::{signal, SignalKind};
 use std::os::unix::fs::PermissionsExt;
+use common::arguments::split_arguments;
 use common::command::Cli;
 use crate::command_handler::CommandHandler;
 use common::paths::SOCKET_PATH;
 use crate::response::Response;
+use crate::socket_client::SocketClient;
 use crate::state::AppState;
+use crate::systemd::Systemd;
 
 pub struct SocketServer {
+    /// Set when the socket is owned by a systemd `.socket` unit, which also sets its permissions.
+    activated: AtomicBool,
 }
 
 impl SocketServer {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            activated: AtomicBool::new(false),
+        }
     }
 
-    pub async fn start(&self, app_state: Arc<AppState>) -> Result<()> {
+    pub async fn bind(&self, mode: u32) -> Result<UnixListener> {
+        if let Some(listener) = Systemd::take_listener()? {
+            self.activated.store(true, Ordering::Relaxed);
+            info!("Socket listener started, using the socket passed by systemd");
+            return Ok(UnixListener::from_std(listener)?);
+        }
+
         _ = fs::remove_file(SOCKET_PATH).await;
 
         let listener = UnixListener::bind(SOCKET_PATH)?;
-        fs::set_permissions(SOCKET_PATH, Permissions::from_mode(0o777)).await?;
+        self.set_permissions(mode).await?;
 
         info!("Socket listener started, listening on {}", SOCKET_PATH);
 
+        Ok(listener)
+    }
+
+    pub async fn set_permissions(&self, mode: u32) -> Result<()> {
+        if self.activated.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        fs::set_permissions(SOCKET_PATH, Permissions::from_mode(mode)).await?;
+        Ok(())
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        // systemd keeps listening on the socket, and starts the daemon again on the next connection
+        if self.activated.load(Ordering::Relaxed) {
+            return Ok(());
+        }
+
+        fs::remove_file(SOCKET_PATH).await?;
+        Ok(())
+    }
+
+    /// Registers the clients that are already waiting to be accepted, e.g. the ones that started
+    /// the daemon through socket activation, so they receive the events that follow.
+    pub async fn accept_pending(&self, listener: &UnixListener, app_state: Arc<AppState>) {
+        while let Some(Ok((stream, _))) = listener.accept().now_or_never() {
+            let (reader, client) = SocketServer::register_client(stream, &app_state).await;
+            tokio::spawn(SocketServer::serve_client(reader, client, Arc::clone(&app_state)));
+        }
+    }
+
+    /// Serves clients until SIGINT or SIGTERM is received. SIGHUP reloads the configuration.
+    pub async fn start(&self, listener: UnixListener, app_state: Arc<AppState>) -> Result<()> {
+        let mut terminate = signal(SignalKind::terminate())?;
+        let mut hangup = signal(SignalKind::hangup())?;
         loop {
             tokio::select! {
                 Ok((stream, _)) = listener.accept() => {
@@ -39,23 +93,65 @@ impl SocketServer {
                     info!("Ctrl+C received, shutting down");
                     break;
                 }
+                _ = terminate.recv() => {
+                    info!("SIGTERM received, shutting down");
+                    break;
+                }
+                _ = hangup.recv() => {
+                    info!("SIGHUP received, reloading the configuration");
+                    let app_state = Arc::clone(&app_state);
+                    tokio::spawn(async move {
+                        if let Err(e) = app_state.reload().await {
+                            error!("Failed to reload the configuration, keeping the current one: {}", e);
+                        }
+                    });
+                }
             }
         }
 
-        return Ok(());
+        Ok(())
+    }
+
+    /// Parses and handles a command line, as sent by the clients of the socket or the WebSocket.
+    pub async fn run_command(command: &str, app_state: Arc<AppState>) -> Option<Response> {
+        info!("Received command: {}", command);
+
+        let received = Instant::now();
+        let command = split_arguments(command)
+            .map_err(|e| format!("Invalid command: {}", e))
+            .and_then(|mut args| {
+                args.insert(0, "ovpn".to_string());
+                Cli::try_parse_from(args).map_err(|_| "Invalid command".to_string())
+            });
+
+        match command {
+            Ok(command) => CommandHandler::handle_command(command, app_state).await.unwrap_or(None),
+            Err(message) => {
+                // Commands that do not parse never reach the handler, so they are counted here
+                app_state.metrics.record_command("invalid", false, received.elapsed());
+                Some(Response::fail(message))
+            }
+        }
     }
 
     async fn handle_client(stream: UnixStream, app_state: Arc<AppState>) {
+        let (reader, client) = SocketServer::register_client(stream, &app_state).await;
+        SocketServer::serve_client(reader, client, app_state).await;
+    }
+
+    async fn register_client(stream: UnixStream, app_state: &AppState) -> (BufReader<OwnedReadHalf>, Arc<Mutex<SocketClient>>) {
         info!("New socket connection");
 
         let (reader, writer) = stream.into_split();
-        let mut reader = BufReader::new(reader);
-        let mut line = String::new();
-
         let mut socket_manager = app_state.socket_manager.lock().await;
         let client = socket_manager.add_client(writer).await;
         drop(socket_manager);
 
+        (BufReader::new(reader), client)
+    }
+
+    async fn serve_client(mut reader: BufReader<OwnedReadHalf>, client: Arc<Mutex<SocketClient>>, app_state: Arc<AppState>) {
+        let mut line = String::new();
         loop {
             line.clear();
 
@@ -66,18 +162,7 @@ impl SocketServer {
                 }
                 Ok(_) => {
                     let command = line.trim_end_matches('\n').trim_end_matches('\r');
-                    info!("Received command: {}", command);
-
-                    let mut args = command.split_whitespace().collect::<Vec<&str>>();
-                    args.insert(0, "ovpn");
-
-                    let response: Option<Response> = match Cli::try_parse_from(args) {
-                        Ok(command) => {
-                            let app_state = app_state.clone();
-                            CommandHandler::handle_command(command, app_state).await.unwrap_or_else(|_| None)
-                        },
-                        Err(_) => Some(Response::fail("Invalid command".to_string()))
-                    };
+                    let response = Self::run_command(command, app_state.clone()).await;
 
                     if let Some(response) = response {
                         let mut client = client.lock().await;
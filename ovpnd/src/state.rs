@@ -1,22 +1,49 @@
 use std::sync::Arc;
+use anyhow::Result;
+use log::info;
+use serde::Serialize;
 use tokio::sync::{Mutex, RwLock};
+use common::paths::DAEMON_CONFIG_PATH;
 use crate::config::ConfigManager;
+use crate::daemon_config::DaemonConfig;
+use crate::dns::DnsManager;
+use crate::logger::Logger;
+use crate::metrics::Metrics;
 use crate::session::Session;
 use crate::session_manager::SessionManager;
 use crate::socket_manager::SocketManager;
 use crate::socket_server::SocketServer;
 
+/// Sections of the daemon configuration that are only read when the daemon starts.
+const RESTART_SECTIONS: [&str; 5] = ["dns", "metrics", "api", "dbus", "websocket"];
+
+/// What a configuration reload changed, by section of the daemon configuration.
+#[derive(Serialize, Debug)]
+pub struct ReloadReport {
+    pub applied: Vec<String>,
+    pub restart_required: Vec<String>,
+}
+
 pub struct AppState {
+    /// Not locked while `config_manager` is, or the other way around, so the two cannot deadlock.
+    pub daemon_config: Arc<RwLock<DaemonConfig>>,
     pub active_session: Arc<RwLock<Option<Session>>>,
     pub config_manager: Arc<RwLock<ConfigManager>>,
     pub session_manager: SessionManager,
+    pub dns_manager: DnsManager,
+    pub metrics: Metrics,
     pub socket_manager: Arc<Mutex<SocketManager>>,
     pub socket_server: SocketServer,
 }
 
 impl AppState {
     pub async fn new() -> Arc<AppState> {
+        let daemon_config = DaemonConfig::load(DAEMON_CONFIG_PATH).expect("Failed to load the daemon configuration");
+
         Arc::new(Self {
+            dns_manager: DnsManager::new(daemon_config.dns_backend),
+            metrics: Metrics::new(),
+            daemon_config: Arc::new(RwLock::new(daemon_config)),
             active_session: Arc::new(RwLock::new(None)),
             config_manager: Arc::new(RwLock::new(ConfigManager::new().await.expect("Failed to load the config manager"))),
             session_manager: SessionManager::new(),
@@ -26,10 +53,37 @@ impl AppState {
     }
 
     pub async fn has_active_session(&self) -> bool {
-        match self.active_session.read().await.as_ref() {
-            Some(_) => true,
-            None => false,
-        }
+        self.active_session.read().await.is_some()
+    }
+
+    /// Re-reads the daemon configuration and the config index. Running sessions keep the
+    /// settings they were started with.
+    pub async fn reload(&self) -> Result<ReloadReport> {
+        let mut new_config = DaemonConfig::load(DAEMON_CONFIG_PATH)?;
+        let config_manager = ConfigManager::new().await?;
+
+        let mut daemon_config = self.daemon_config.write().await;
+        let (restart_required, applied): (Vec<_>, Vec<_>) = daemon_config.changed_sections(&new_config).into_iter()
+            .map(|section| section.to_string())
+            .partition(|section| RESTART_SECTIONS.contains(&section.as_str()));
+
+        // Settings that only take effect after a restart keep reporting as changed until then
+        new_config.dns_backend = daemon_config.dns_backend;
+        new_config.metrics = daemon_config.metrics.clone();
+        new_config.api = daemon_config.api.clone();
+        new_config.dbus = daemon_config.dbus.clone();
+        new_config.websocket = daemon_config.websocket.clone();
+
+        log::set_max_level(new_config.log_level);
+        Logger::set_backend(new_config.log_backend);
+        self.socket_server.set_permissions(new_config.socket_mode).await?;
+        *daemon_config = new_config;
+        drop(daemon_config);
+        *self.config_manager.write().await = config_manager;
+
+        let report = ReloadReport { applied, restart_required };
+        info!("Configuration reloaded, applied: {:?}, requires a restart: {:?}", report.applied, report.restart_required);
+        Ok(report)
     }
 }
 
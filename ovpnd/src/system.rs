@@ -0,0 +1,51 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+use anyhow::{anyhow, Context, Result};
+
+/// Runs a system tool to completion, optionally feeding it `stdin`, and returns its stdout.
+/// This blocks, so async callers should use `spawn_blocking`.
+pub fn run(program: &str, arguments: &[&str], stdin: Option<&str>) -> Result<String> {
+    let mut child = Command::new(program)
+        .args(arguments)
+        .stdin(if stdin.is_some() { Stdio::piped() } else { Stdio::null() })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("Failed to run {}", program))?;
+
+    if let (Some(input), Some(mut pipe)) = (stdin, child.stdin.take()) {
+        pipe.write_all(input.as_bytes())?;
+    }
+
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(anyhow!("{} {} failed: {}", program, arguments.join(" "), String::from_utf8_lossy(&output.stderr).trim()));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+/// Returns the start time of a process in clock ticks after boot, or `None` if it does not exist.
+/// Together with the pid, this identifies a process even if the pid is reused later.
+pub fn process_start_time(pid: u32) -> Option<u64> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name can contain spaces and parentheses, so the fields are counted from its end
+    let fields = stat.rsplit_once(')')?.1.split_whitespace().collect::<Vec<_>>();
+    fields.get(19)?.parse().ok()
+}
+
+/// Returns the command line of a process.
+pub fn process_command_line(pid: u32) -> Option<Vec<String>> {
+    let command_line = std::fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    Some(command_line.split(|b| *b == 0)
+        .filter(|argument| !argument.is_empty())
+        .map(|argument| String::from_utf8_lossy(argument).to_string())
+        .collect())
+}
+
+/// Whether the process exists and has not exited yet (zombies count as exited).
+pub fn is_process_running(pid: u32) -> bool {
+    std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()
+        .and_then(|stat| stat.rsplit_once(')').map(|(_, fields)| fields.trim_start().to_string()))
+        .is_some_and(|fields| !fields.starts_with('Z') && !fields.starts_with('X'))
+}
@@ -0,0 +1,175 @@
+use std::env;
+use std::os::fd::{FromRawFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram, UnixListener};
+use std::time::Duration;
+use anyhow::{Context, Result};
+use log::warn;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+
+/// The first file descriptor passed by systemd socket activation.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// The parts of the systemd service protocol used by the daemon, see sd_notify(3) and
+/// sd_listen_fds(3). Everything is a no-op when the daemon is not started by systemd.
+pub struct Systemd {}
+
+impl Systemd {
+    /// Sends a state update such as `READY=1` or `STATUS=...` to the service manager.
+    pub fn notify(state: &str) -> Result<()> {
+        let Ok(path) = env::var("NOTIFY_SOCKET") else { return Ok(()) };
+
+        let socket = UnixDatagram::unbound()?;
+        match path.strip_prefix('@') {
+            Some(name) => socket.send_to_addr(state.as_bytes(), &SocketAddr::from_abstract_name(name)?)?,
+            None => socket.send_to(state.as_bytes(), &path)?,
+        };
+
+        Ok(())
+    }
+
+    /// How often the service manager expects `WATCHDOG=1`, if the watchdog is enabled.
+    pub fn watchdog_interval() -> Option<Duration> {
+        if let Ok(pid) = env::var("WATCHDOG_PID") {
+            if pid.parse::<u32>().ok() != Some(std::process::id()) {
+                return None;
+            }
+        }
+
+        let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+        (usec > 0).then(|| Duration::from_micros(usec))
+    }
+
+    /// Takes the listening socket passed by a `.socket` unit, if any. The activation variables
+    /// are removed, so they are not inherited by openvpn.
+    pub fn take_listener() -> Result<Option<UnixListener>> {
+        let count = Self::listen_fds(env::var("LISTEN_PID").ok().as_deref(), env::var("LISTEN_FDS").ok().as_deref());
+        for name in ["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"] {
+            env::remove_var(name);
+        }
+
+        if count < 1 {
+            return Ok(None);
+        }
+        if count > 1 {
+            warn!("systemd passed {} sockets, only the first one is used", count);
+        }
+
+        fcntl(LISTEN_FDS_START, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+            .context("Failed to configure the socket passed by systemd")?;
+        let listener = unsafe { UnixListener::from_raw_fd(LISTEN_FDS_START) };
+        listener.set_nonblocking(true)?;
+
+        Ok(Some(listener))
+    }
+
+    /// The number of sockets passed to this process, given the `LISTEN_PID` and `LISTEN_FDS`
+    /// variables. Sockets meant for another process are ignored.
+    fn listen_fds(pid: Option<&str>, count: Option<&str>) -> i32 {
+        if pid.and_then(|pid| pid.parse::<u32>().ok()) != Some(std::process::id()) {
+            return 0;
+        }
+
+        count.and_then(|count| count.parse::<i32>().ok()).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use super::*;
+
+    /// The tests change the environment of the whole process, so they must not run concurrently.
+    static ENVIRONMENT: Mutex<()> = Mutex::new(());
+
+    fn receive(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 256];
+        let length = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..length]).to_string()
+    }
+
+    #[test]
+    fn notifies_path_socket() {
+        let _guard = ENVIRONMENT.lock().unwrap();
+        let directory = env::temp_dir().join(format!("ovpnd-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("notify.sock");
+        _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+
+        env::set_var("NOTIFY_SOCKET", &path);
+        let states = ["READY=1\nSTATUS=No active session", "STATUS=Session 'work' is Running", "WATCHDOG=1"];
+        let sent = states.iter().map(|state| Systemd::notify(state)).collect::<Vec<_>>();
+        env::remove_var("NOTIFY_SOCKET");
+        std::fs::remove_dir_all(&directory).unwrap();
+
+        assert!(sent.iter().all(|result| result.is_ok()));
+        for state in states {
+            assert_eq!(receive(&socket), state);
+        }
+    }
+
+    #[test]
+    fn notifies_abstract_socket() {
+        let _guard = ENVIRONMENT.lock().unwrap();
+        let name = format!("ovpnd-notify-{}", std::process::id());
+        let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+
+        env::set_var("NOTIFY_SOCKET", format!("@{}", name));
+        let sent = Systemd::notify("WATCHDOG=1");
+        env::remove_var("NOTIFY_SOCKET");
+
+        sent.unwrap();
+        assert_eq!(receive(&socket), "WATCHDOG=1");
+    }
+
+    #[test]
+    fn skips_notify_without_socket() {
+        let _guard = ENVIRONMENT.lock().unwrap();
+        env::remove_var("NOTIFY_SOCKET");
+
+        assert!(Systemd::notify("READY=1").is_ok());
+    }
+
+    #[test]
+    fn reads_watchdog_interval() {
+        let _guard = ENVIRONMENT.lock().unwrap();
+        env::set_var("WATCHDOG_USEC", "30000000");
+        env::set_var("WATCHDOG_PID", std::process::id().to_string());
+        let own = Systemd::watchdog_interval();
+        env::set_var("WATCHDOG_PID", "1");
+        let other = Systemd::watchdog_interval();
+        env::remove_var("WATCHDOG_PID");
+        env::set_var("WATCHDOG_USEC", "0");
+        let disabled = Systemd::watchdog_interval();
+        env::remove_var("WATCHDOG_USEC");
+
+        assert_eq!(own, Some(Duration::from_secs(30)));
+        assert_eq!(other, None);
+        assert_eq!(disabled, None);
+    }
+
+    #[test]
+    fn counts_listen_fds() {
+        let pid = std::process::id().to_string();
+
+        assert_eq!(Systemd::listen_fds(Some(&pid), Some("1")), 1);
+        assert_eq!(Systemd::listen_fds(Some(&pid), Some("2")), 2);
+        assert_eq!(Systemd::listen_fds(Some(&pid), None), 0);
+        assert_eq!(Systemd::listen_fds(Some(&pid), Some("many")), 0);
+        assert_eq!(Systemd::listen_fds(Some("1"), Some("1")), 0);
+        assert_eq!(Systemd::listen_fds(None, Some("1")), 0);
+    }
+
+    #[test]
+    fn ignores_sockets_of_other_processes() {
+        let _guard = ENVIRONMENT.lock().unwrap();
+        env::set_var("LISTEN_PID", "1");
+        env::set_var("LISTEN_FDS", "1");
+        env::set_var("LISTEN_FDNAMES", "ovpnd.socket");
+        let listener = Systemd::take_listener().unwrap();
+
+        assert!(listener.is_none());
+        assert!(["LISTEN_PID", "LISTEN_FDS", "LISTEN_FDNAMES"].iter().all(|name| env::var(name).is_err()));
+    }
+}
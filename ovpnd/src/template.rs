@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use anyhow::{anyhow, Result};
+
+pub struct Template {}
+
+impl Template {
+    /// Returns the names of all `{{var}}` placeholders, in the order they first appear.
+    pub fn variables(text: &str) -> Vec<String> {
+        let mut variables = Vec::new();
+        Self::substitute(text, |name| {
+            if !variables.iter().any(|v| v == name) {
+                variables.push(name.to_string());
+            }
+            None
+        });
+
+        variables
+    }
+
+    /// Replaces every `{{var}}` placeholder with its value, failing if any of them is not set.
+    /// Values cannot contain control characters, so they cannot add lines to the config.
+    pub fn render(text: &str, values: &BTreeMap<String, String>) -> Result<String> {
+        let missing = Self::variables(text).into_iter()
+            .filter(|v| !values.contains_key(v))
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(anyhow!("The following template variables are not set: {}", missing.join(", ")));
+        }
+
+        let invalid = values.iter()
+            .filter(|(_, value)| value.chars().any(char::is_control))
+            .map(|(name, _)| name.as_str())
+            .collect::<Vec<_>>();
+        if !invalid.is_empty() {
+            return Err(anyhow!("The following template variables contain control characters: {}", invalid.join(", ")));
+        }
+
+        Ok(Self::substitute(text, |name| values.get(name).cloned()))
+    }
+
+    /// Calls `replace` for every placeholder; placeholders it returns `None` for are kept as-is.
+    fn substitute<F>(text: &str, mut replace: F) -> String
+    where
+        F: FnMut(&str) -> Option<String>,
+    {
+        let mut output = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find("{{") {
+            output.push_str(&rest[..start]);
+            let after_open = &rest[start + 2..];
+            let Some(end) = after_open.find("}}") else {
+                rest = &rest[start..];
+                break;
+            };
+
+            let name = after_open[..end].trim();
+            let is_variable = !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+            match is_variable.then(|| replace(name)).flatten() {
+                Some(value) => output.push_str(&value),
+                None => output.push_str(&rest[start..start + 2 + end + 2]),
+            }
+            rest = &after_open[end + 2..];
+        }
+        output.push_str(rest);
+
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPLATE: &str = "client\nremote {{ host }} {{port}}\nverify-x509-name {{host}} name\nsetenv UV_DATA {{not a variable}} {{}}\ntls-version-min 1.2 {{open\n";
+
+    fn values(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    #[test]
+    fn lists_the_variables_once() {
+        assert_eq!(Template::variables(TEMPLATE), ["host", "port"]);
+    }
+
+    #[test]
+    fn substitutes_every_placeholder() {
+        let rendered = Template::render(TEMPLATE, &values(&[("host", "vpn.example.com"), ("port", "443")])).unwrap();
+
+        assert_eq!(rendered, "client\nremote vpn.example.com 443\nverify-x509-name vpn.example.com name\nsetenv UV_DATA {{not a variable}} {{}}\ntls-version-min 1.2 {{open\n");
+    }
+
+    #[test]
+    fn fails_on_missing_variables() {
+        let error = Template::render(TEMPLATE, &BTreeMap::new()).unwrap_err();
+
+        assert_eq!(error.to_string(), "The following template variables are not set: host, port");
+    }
+
+    #[test]
+    fn ignores_unused_variables() {
+        let rendered = Template::render("remote {{host}}\n", &values(&[("host", "vpn.example.com"), ("port", "443")])).unwrap();
+
+        assert_eq!(rendered, "remote vpn.example.com\n");
+    }
+
+    #[test]
+    fn uses_the_last_of_duplicate_variables() {
+        // The same way `config profile` collects its --var arguments
+        let variables = values(&[("host", "old.example.com"), ("host", "vpn.example.com")]);
+
+        assert_eq!(Template::render("remote {{host}}\n", &variables).unwrap(), "remote vpn.example.com\n");
+    }
+
+    #[test]
+    fn rejects_control_characters() {
+        let error = Template::render("remote {{host}}\n", &values(&[("host", "vpn.example.com\nup /tmp/x.sh")])).unwrap_err();
+
+        assert_eq!(error.to_string(), "The following template variables contain control characters: host");
+    }
+}
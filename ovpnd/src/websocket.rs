@@ -0,0 +1,246 @@
+use std::sync::Arc;
+use anyhow::{anyhow, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use log::{error, info};
+use serde_json::json;
+use sha1::{Digest, Sha1};
+use tokio::io::{split, AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc;
+use crate::daemon_config::WebSocketSettings;
+use crate::events::Event;
+use crate::http::{constant_time_eq, Connection, HttpListener, HttpRequest, HttpResponse};
+use crate::socket_server::SocketServer;
+use crate::state::AppState;
+
+/// Appended to the key of the client to compute the accept header, see RFC 6455.
+const HANDSHAKE_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Largest command a client can send, commands are short lines.
+const MAX_MESSAGE_SIZE: usize = 64 * 1024;
+
+const OPCODE_CONTINUATION: u8 = 0x0;
+const OPCODE_TEXT: u8 = 0x1;
+const OPCODE_BINARY: u8 = 0x2;
+const OPCODE_CLOSE: u8 = 0x8;
+const OPCODE_PING: u8 = 0x9;
+const OPCODE_PONG: u8 = 0xA;
+
+/// Status code of a close frame that ends the connection normally.
+const CLOSE_NORMAL: u16 = 1000;
+
+type Reader = ReadHalf<Box<dyn Connection>>;
+type Writer = WriteHalf<Box<dyn Connection>>;
+
+/// A complete message received from a client.
+enum Message {
+    Text(String),
+    Ping(Vec<u8>),
+    Close,
+}
+
+/// Bridges the socket protocol to WebSocket clients, such as widgets running in a browser or
+/// a JS runtime. Text messages are commands in the same format as on the socket, and every
+/// message sent to the client is a JSON object with a `type`: `response`, or the name of an
+/// event (`status`, `traffic` or `daemon`) with the event in `data`.
+pub struct WebSocket {}
+
+impl WebSocket {
+    /// Serves the bridge on the configured address, until the daemon exits.
+    pub async fn serve(settings: WebSocketSettings, app_state: Arc<AppState>) {
+        let Some(address) = &settings.listen else { return };
+        let listener = match HttpListener::bind(address, settings.mode).await {
+            Ok(listener) => listener,
+            Err(e) => return error!("Failed to start the WebSocket bridge: {}", e),
+        };
+        info!("WebSocket bridge listening on {}", address);
+
+        let settings = Arc::new(settings);
+        loop {
+            match listener.accept().await {
+                Ok(stream) => { tokio::spawn(Self::handle_connection(stream, Arc::clone(&settings), Arc::clone(&app_state))); }
+                Err(e) => error!("Failed to accept a WebSocket connection: {}", e),
+            }
+        }
+    }
+
+    async fn handle_connection(mut stream: Box<dyn Connection>, settings: Arc<WebSocketSettings>, app_state: Arc<AppState>) {
+        let Ok(request) = HttpRequest::read(stream.as_mut(), 0).await else { return };
+        let key = match Self::check_handshake(&request, &settings) {
+            Ok(key) => key,
+            Err((status, message)) => {
+                _ = HttpResponse::new(status, "text/plain", format!("{}\n", message)).send(stream.as_mut()).await;
+                return;
+            }
+        };
+
+        let accept = BASE64_STANDARD.encode(Sha1::digest(format!("{}{}", key, HANDSHAKE_GUID)));
+        let response = format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n", accept);
+        if stream.write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+
+        info!("WebSocket client connected");
+        match Self::run(stream, app_state).await {
+            Ok(_) => info!("WebSocket client disconnected"),
+            Err(e) => info!("WebSocket client disconnected: {}", e),
+        }
+    }
+
+    /// Returns the key of a valid upgrade request from an allowed client.
+    fn check_handshake(request: &HttpRequest, settings: &WebSocketSettings) -> Result<String, (&'static str, &'static str)> {
+        let is_upgrade = request.header("upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket"));
+        if request.method != "GET" || !request.path.is_empty() || !is_upgrade {
+            return Err(("400 Bad Request", "Expected a WebSocket upgrade request on /"));
+        }
+        if request.header("sec-websocket-version") != Some("13") {
+            return Err(("426 Upgrade Required", "Only WebSocket version 13 is supported"));
+        }
+        let key = request.header("sec-websocket-key").ok_or(("400 Bad Request", "The Sec-WebSocket-Key header is missing"))?;
+
+        // Browsers send the origin of the page, other clients usually don't send one at all
+        if let Some(origin) = request.header("origin") {
+            if !settings.allowed_origins.iter().any(|allowed| allowed == "*" || allowed == origin) {
+                return Err(("403 Forbidden", "The origin is not allowed"));
+            }
+        }
+        if let Some(token) = &settings.token {
+            if !request.query("token").is_some_and(|value| constant_time_eq(value.as_bytes(), token.as_bytes())) {
+                return Err(("401 Unauthorized", "A valid token is required"));
+            }
+        }
+
+        Ok(key.to_string())
+    }
+
+    /// Sends the status of the active session, then handles commands and forwards events until
+    /// the client goes away.
+    async fn run(stream: Box<dyn Connection>, app_state: Arc<AppState>) -> Result<()> {
+        let (reader, mut writer) = split(stream);
+        let mut events = app_state.socket_manager.lock().await.subscribe();
+
+        // Frames are read by their own task, since a partially read frame cannot be resumed
+        let (sender, mut messages) = mpsc::channel(8);
+        let reader = tokio::spawn(Self::read_messages(reader, sender));
+
+        let current = match app_state.active_session.read().await.as_ref() {
+            Some(session) => Some(Event::Status(Box::new(session.to_serializable().await))),
+            None => None,
+        };
+        let result = async {
+            if let Some(event) = current {
+                Self::send_event(&mut writer, &event).await?;
+            }
+
+            loop {
+                tokio::select! {
+                    message = messages.recv() => match message {
+                        Some(Ok(Message::Text(command))) => {
+                            let Some(response) = SocketServer::run_command(command.trim(), Arc::clone(&app_state)).await else { continue };
+                            let message = json!({ "type": "response", "success": response.is_success(), "message": response.message() });
+                            Self::write_frame(&mut writer, OPCODE_TEXT, message.to_string().as_bytes()).await?;
+                        }
+                        Some(Ok(Message::Ping(data))) => Self::write_frame(&mut writer, OPCODE_PONG, &data).await?,
+                        Some(Ok(Message::Close)) | None => {
+                            Self::write_frame(&mut writer, OPCODE_CLOSE, &CLOSE_NORMAL.to_be_bytes()).await?;
+                            return Ok(());
+                        }
+                        Some(Err(e)) => return Err(e),
+                    },
+                    event = events.recv() => match event {
+                        Ok(event) => Self::send_event(&mut writer, &event).await?,
+                        Err(RecvError::Lagged(_)) => {}
+                        Err(RecvError::Closed) => return Ok(()),
+                    },
+                }
+            }
+        }.await;
+        reader.abort();
+
+        result
+    }
+
+    async fn read_messages(mut reader: Reader, sender: mpsc::Sender<Result<Message>>) {
+        let mut fragments = Vec::new();
+        loop {
+            let message = Self::read_message(&mut reader, &mut fragments).await;
+            let end = !matches!(message, Ok(Message::Text(_) | Message::Ping(_)));
+            if sender.send(message).await.is_err() || end {
+                return;
+            }
+        }
+    }
+
+    /// Reads frames until a message is complete. Text split over several frames is collected
+    /// in `fragments`, control frames may arrive in between.
+    async fn read_message(reader: &mut Reader, fragments: &mut Vec<u8>) -> Result<Message> {
+        loop {
+            let mut head = [0u8; 2];
+            reader.read_exact(&mut head).await?;
+            let is_final = head[0] & 0x80 != 0;
+            let opcode = head[0] & 0x0F;
+            let is_masked = head[1] & 0x80 != 0;
+            let length = match head[1] & 0x7F {
+                126 => reader.read_u16().await? as u64,
+                127 => reader.read_u64().await?,
+                length => length as u64,
+            };
+
+            if !is_masked {
+                return Err(anyhow!("The client sent an unmasked frame"));
+            }
+            if length > (MAX_MESSAGE_SIZE - fragments.len()) as u64 {
+                return Err(anyhow!("The message is larger than {} bytes", MAX_MESSAGE_SIZE));
+            }
+
+            let mut mask = [0u8; 4];
+            reader.read_exact(&mut mask).await?;
+            let mut payload = vec![0u8; length as usize];
+            reader.read_exact(&mut payload).await?;
+            for (index, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[index % 4];
+            }
+
+            match opcode {
+                OPCODE_TEXT | OPCODE_CONTINUATION => {
+                    fragments.extend_from_slice(&payload);
+                    if is_final {
+                        let text = String::from_utf8(std::mem::take(fragments))?;
+                        return Ok(Message::Text(text));
+                    }
+                }
+                OPCODE_PING => return Ok(Message::Ping(payload)),
+                OPCODE_PONG => {}
+                OPCODE_CLOSE => return Ok(Message::Close),
+                OPCODE_BINARY => return Err(anyhow!("Binary messages are not supported, commands are sent as text")),
+                _ => return Err(anyhow!("Unknown opcode {}", opcode)),
+            }
+        }
+    }
+
+    async fn send_event(writer: &mut Writer, event: &Event) -> Result<()> {
+        let message = format!("{{\"type\":\"{}\",\"data\":{}}}", event.name(), event.to_json()?);
+        Self::write_frame(writer, OPCODE_TEXT, message.as_bytes()).await
+    }
+
+    /// Writes a single unmasked frame, as servers do.
+    async fn write_frame(writer: &mut Writer, opcode: u8, payload: &[u8]) -> Result<()> {
+        let mut frame = vec![0x80 | opcode];
+        match payload.len() {
+            length if length < 126 => frame.push(length as u8),
+            length if length <= u16::MAX as usize => {
+                frame.push(126);
+                frame.extend_from_slice(&(length as u16).to_be_bytes());
+            }
+            length => {
+                frame.push(127);
+                frame.extend_from_slice(&(length as u64).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(payload);
+
+        writer.write_all(&frame).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+}